@@ -16,8 +16,6 @@
             cargo-outdated
             clippy
             rustfmt
-            entr
-            caddy
           ];
         });
       });
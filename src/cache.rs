@@ -0,0 +1,132 @@
+//! Build cache for only rewriting output files whose inputs have changed.
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    fs,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+
+/// Suffix of the cache file next to the output directory, which is
+/// `.public_html.build-cache` for `public_html`.
+pub const CACHE_SUFFIX: &str = "build-cache";
+
+/// Hashes of the input data and the templates an output file was built
+/// from.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Stamp {
+    pub input: u64,
+    pub template: u64,
+}
+
+impl Stamp {
+    /// Stamp for data rendered with templates with the given sources.
+    pub fn new(input: &impl Hash, templates: &[&str]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        // Changes in the engine itself can change the output too.
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        templates.hash(&mut hasher);
+
+        Stamp {
+            input: hash(input),
+            template: hasher.finish(),
+        }
+    }
+}
+
+/// Stamps of the files written in the previous build.
+#[derive(Clone, Default, Debug)]
+pub struct BuildCache(BTreeMap<String, Stamp>);
+
+impl BuildCache {
+    /// Read a cache file.
+    ///
+    /// A missing or unreadable cache is treated as empty, so everything gets
+    /// rebuilt.
+    pub fn load(path: &Path) -> Self {
+        let Ok(text) = fs::read_to_string(path) else {
+            return Default::default();
+        };
+
+        let parse = |line: &str| {
+            let mut parts = line.splitn(3, ' ');
+            let input = u64::from_str_radix(parts.next()?, 16).ok()?;
+            let template = u64::from_str_radix(parts.next()?, 16).ok()?;
+            Some((parts.next()?.to_owned(), Stamp { input, template }))
+        };
+        BuildCache(text.lines().filter_map(parse).collect())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut text = String::new();
+        for (path, stamp) in &self.0 {
+            text.push_str(&format!(
+                "{:016x} {:016x} {path}\n",
+                stamp.input, stamp.template
+            ));
+        }
+        fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn get(&self, path: &str) -> Option<Stamp> {
+        self.0.get(path).copied()
+    }
+
+    pub fn insert(&mut self, path: impl Into<String>, stamp: Stamp) {
+        self.0.insert(path.into(), stamp);
+    }
+
+    /// Whether an output file needs to be written for the given stamp.
+    pub fn is_stale(&self, output: &Path, path: &str, stamp: Stamp) -> bool {
+        self.get(path) != Some(stamp) || !output.join(path).is_file()
+    }
+}
+
+/// Hash a value for change detection.
+pub fn hash(value: &(impl Hash + ?Sized)) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn incremental_write() {
+        // Caches go next to the output, so it's in a directory of its own.
+        let dir = TempDir::new("cache");
+        let output = dir.join("site");
+        let site = crate::Site::default();
+
+        crate::write(&site, &output).unwrap();
+        // The cache isn't published with the site.
+        let cache = crate::output::sibling(&output, CACHE_SUFFIX).unwrap();
+        assert!(cache.is_file());
+        assert!(!output.join(".build-cache").exists());
+        let index = output.join("index.html");
+        let mtime = fs::metadata(&index).unwrap().modified().unwrap();
+        fs::create_dir_all(output.join("stale")).unwrap();
+        fs::write(output.join("stale/page.html"), "").unwrap();
+
+        crate::write(&site, &output).unwrap();
+        assert_eq!(fs::metadata(&index).unwrap().modified().unwrap(), mtime);
+        assert!(!output.join("stale").exists());
+
+        // Directories that aren't site output are left alone.
+        let other = dir.join("other");
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join("notes.txt"), "").unwrap();
+        assert!(crate::write(&site, &other).is_err());
+        assert!(other.join("notes.txt").exists());
+
+        // Files deleted from the output are written again.
+        fs::remove_file(&index).unwrap();
+        crate::write(&site, &output).unwrap();
+        assert!(index.exists());
+    }
+}
@@ -0,0 +1,97 @@
+//! Posts and link notes written in Djot markup.
+
+use anyhow::Result;
+use jotdown::{Attributes, Container, Event, Parser};
+use latex2mathml::DisplayStyle;
+
+use crate::markdown;
+
+/// Convert Djot to HTML.
+///
+/// Attributes and divs become HTML classes and ids, code blocks are
+/// highlighted like markdown code blocks and math is turned into MathML.
+pub fn to_html(text: &str) -> Result<String> {
+    let mut events = Vec::new();
+    // Language and text of the code block being read.
+    let mut code: Option<(String, String)> = None;
+    // TeX of the math being read.
+    let mut math: Option<String> = None;
+
+    for event in Parser::new(text) {
+        match event {
+            Event::Start(Container::CodeBlock { language }, _) => {
+                code = Some((language.to_string(), String::new()));
+            }
+            Event::End(Container::CodeBlock { .. }) => {
+                if let Some((lang, buf)) = code.take() {
+                    push_html(&mut events, false, markdown::code_block(&lang, &buf));
+                }
+            }
+            Event::Start(Container::Math { .. }, _) => math = Some(String::new()),
+            Event::End(Container::Math { display }) => {
+                if let Some(tex) = math.take() {
+                    let style = if display {
+                        DisplayStyle::Block
+                    } else {
+                        DisplayStyle::Inline
+                    };
+                    push_html(&mut events, true, markdown::math(&tex, style)?);
+                }
+            }
+            Event::Str(a) => match (&mut code, &mut math) {
+                (Some((_, buf)), _) | (_, Some(buf)) => buf.push_str(&a),
+                _ => events.push(Event::Str(a)),
+            },
+            event => events.push(event),
+        }
+    }
+
+    Ok(jotdown::html::render_to_string(events.into_iter()))
+}
+
+/// Add HTML that is output as is.
+fn push_html(events: &mut Vec<Event<'_>>, is_inline: bool, html: String) {
+    let container = || {
+        if is_inline {
+            Container::RawInline {
+                format: "html".into(),
+            }
+        } else {
+            Container::RawBlock {
+                format: "html".into(),
+            }
+        }
+    };
+    events.push(Event::Start(container(), Attributes::new()));
+    events.push(Event::Str(html.into()));
+    events.push(Event::End(container()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn djot() {
+        let text = "\
+::: warning
+Careful with *nested _emphasis_*{.note}.
+:::
+
+``` rust
+fn main() {}
+```
+
+Euler: $`e^{i\\pi} = -1`
+";
+        let html = to_html(text).unwrap();
+        assert!(html.contains("<div class=\"warning\">"));
+        assert!(html.contains("<strong class=\"note\">nested <em>emphasis</em></strong>"));
+        assert!(html.contains("<pre class=\"hl-code\"><code class=\"language-rust\">"));
+        assert!(
+            html.contains("<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"inline\">")
+        );
+        // Raw HTML only passes through when marked as such.
+        assert!(to_html("<b>").unwrap().contains("&lt;b&gt;"));
+    }
+}
@@ -0,0 +1,532 @@
+//! EPUB books of the site's posts.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    io::{Cursor, Write as _},
+};
+
+use anyhow::{bail, Result};
+use ego_tree::NodeRef;
+use lazy_regex::regex;
+use scraper::{Html, Node};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    input::Status,
+    output::{mime_type, OutputTree},
+    util::{self, escape_html as escape},
+    Post, Site,
+};
+
+/// Stylesheet rules for the chapters on top of the site's font faces.
+const STYLE: &str = r#"body { font-family: "Clear Sans", sans-serif; line-height: 1.4; }
+code { font-family: "Intel One Mono", monospace; }
+pre { white-space: pre-wrap; font-size: 0.8em; }
+h1 { text-align: center; font-size: 1.5em; }
+.date { color: gray; text-align: center; }
+.anchor, .sidenote-toggle { display: none; }
+"#;
+
+/// Bundle the published posts of a site into an EPUB 3 book.
+///
+/// Posts are in date order, and only the ones with `tag` are included if
+/// it's given. Images and fonts the posts and the stylesheet use are taken
+/// from the site's static files, and links between the posts in the book
+/// point to their chapters.
+pub fn epub(site: &Site, tag: Option<&str>) -> Result<Vec<u8>> {
+    let mut posts: Vec<&Post> = site
+        .posts
+        .values()
+        .filter(|a| a.status == Status::Published)
+        .filter(|a| tag.is_none_or(|t| a.tags.iter().any(|b| b == t)))
+        .collect();
+    if posts.is_empty() {
+        match tag {
+            Some(tag) => bail!("No published posts tagged `{tag}`"),
+            None => bail!("No published posts"),
+        }
+    }
+    posts.sort_by(|a, b| (&a.feed_date, &a.slug).cmp(&(&b.feed_date, &b.slug)));
+
+    let tree = site.output()?;
+    let book = Book {
+        site,
+        tree: &tree,
+        slugs: posts.iter().map(|a| a.slug.as_str()).collect(),
+    };
+
+    // Files under the content directory by path.
+    let mut files: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    let mut resources: BTreeSet<String> = BTreeSet::new();
+
+    let mut css = String::new();
+    if let Some(style) = tree.get("style.css") {
+        let style = String::from_utf8_lossy(&style.data()?).into_owned();
+        for face in regex!(r"@font-face\s*\{[^}]*\}").find_iter(&style) {
+            css.push_str(face.as_str());
+            css.push('\n');
+            for url in regex!(r#"url\(\s*['"]?(?:\./)?([^'")]+)"#).captures_iter(face.as_str()) {
+                book.add_resource(&url[1], &mut resources);
+            }
+        }
+    }
+    css.push_str(STYLE);
+    css.push_str(&site.highlight_css);
+    files.insert("style.css".into(), css.into_bytes());
+
+    for post in &posts {
+        let content = book.chapter(post, &mut resources);
+        files.insert(format!("{}.xhtml", post.slug), content.into_bytes());
+    }
+    files.insert("nav.xhtml".into(), book.nav(&posts).into_bytes());
+    for path in &resources {
+        files.insert(path.clone(), tree.get(path).unwrap().data()?.into_owned());
+    }
+    files.insert(
+        "content.opf".into(),
+        book.package(&posts, tag, &files).into_bytes(),
+    );
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // The media type must come first and uncompressed.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/epub+zip")?;
+    let options = SimpleFileOptions::default();
+    zip.start_file("META-INF/container.xml", options)?;
+    zip.write_all(CONTAINER.as_bytes())?;
+    for (path, data) in &files {
+        zip.start_file(format!("OEBPS/{path}"), options)?;
+        zip.write_all(data)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+struct Book<'a> {
+    site: &'a Site,
+    tree: &'a OutputTree<'a>,
+    /// Posts that are in the book.
+    slugs: BTreeSet<&'a str>,
+}
+
+impl Book<'_> {
+    fn chapter(&self, post: &Post, resources: &mut BTreeSet<String>) -> String {
+        let content = to_xhtml(&post.content, |name, url| match name {
+            "href" => self.link(url),
+            _ => self.image(url, resources),
+        });
+        let mut ret = xhtml_head(&self.site.index.chrome.lang, &post.title);
+        let _ = writeln!(ret, "<h1>{}</h1>", escape(&post.title));
+        if !post.date.is_empty() {
+            let _ = writeln!(ret, "<p class=\"date\">{}</p>", escape(&post.date));
+        }
+        ret.push_str(&content);
+        ret.push_str("\n</body>\n</html>\n");
+        ret
+    }
+
+    /// Point links to posts in the book at their chapters and other site
+    /// links at the web site.
+    fn link(&self, url: &str) -> String {
+        let local = self.local_path(url);
+        if local.is_empty() || url.starts_with('#') {
+            return url.to_owned();
+        }
+        let (path, anchor) = match local.find('#') {
+            Some(i) => local.split_at(i),
+            None => (local, ""),
+        };
+        let slug = path.trim_start_matches('/').trim_end_matches(".html");
+        if self.slugs.contains(slug) {
+            format!("{slug}.xhtml{anchor}")
+        } else if is_relative(url) {
+            format!("{}{}", self.site.base_url, local.trim_start_matches('/'))
+        } else {
+            url.to_owned()
+        }
+    }
+
+    /// Include an image from the site's files in the book.
+    fn image(&self, url: &str, resources: &mut BTreeSet<String>) -> String {
+        let path = self.local_path(url).trim_start_matches('/');
+        if mime_type(path).starts_with("image/") && self.add_resource(path, resources) {
+            path.to_owned()
+        } else {
+            url.to_owned()
+        }
+    }
+
+    fn add_resource(&self, path: &str, resources: &mut BTreeSet<String>) -> bool {
+        if self.tree.get(path).is_none() {
+            return false;
+        }
+        resources.insert(path.to_owned());
+        true
+    }
+
+    /// Path of a URL on the site, or empty if it points elsewhere.
+    fn local_path<'b>(&self, url: &'b str) -> &'b str {
+        match url.strip_prefix(&self.site.base_url) {
+            Some(path) if !self.site.base_url.is_empty() => path,
+            _ if is_relative(url) => url.trim_start_matches("./"),
+            _ => "",
+        }
+    }
+
+    fn nav(&self, posts: &[&Post]) -> String {
+        let mut ret = xhtml_head(&self.site.index.chrome.lang, &self.site.index.title);
+        let _ = writeln!(
+            ret,
+            "<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>"
+        );
+        for post in posts {
+            let _ = writeln!(
+                ret,
+                "<li><a href=\"{}.xhtml\">{}</a></li>",
+                post.slug,
+                escape(&post.title)
+            );
+        }
+        ret.push_str("</ol>\n</nav>\n</body>\n</html>\n");
+        ret
+    }
+
+    fn package(
+        &self,
+        posts: &[&Post],
+        tag: Option<&str>,
+        files: &BTreeMap<String, Vec<u8>>,
+    ) -> String {
+        let site = self.site;
+        let title = match tag {
+            Some(tag) => format!("{}: {tag}", site.index.title),
+            None => site.index.title.clone(),
+        };
+        let id = md5::compute(format!("{}\n{}", site.base_url, tag.unwrap_or("")));
+        let id = format!("{id:x}");
+        let modified = posts
+            .iter()
+            .map(|a| a.feed_date.as_str())
+            .max()
+            .unwrap_or(util::EPOCH);
+
+        let mut ret = String::new();
+        let _ = writeln!(
+            ret,
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:identifier id="id">urn:uuid:{}-{}-{}-{}-{}</dc:identifier>
+<dc:title>{}</dc:title>
+<dc:creator>{}</dc:creator>
+<dc:language>{}</dc:language>
+<meta property="dcterms:modified">{modified}</meta>
+</metadata>
+<manifest>
+<item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>"#,
+            &id[..8],
+            &id[8..12],
+            &id[12..16],
+            &id[16..20],
+            &id[20..],
+            escape(&title),
+            escape(&site.author),
+            escape(&site.index.chrome.lang),
+        );
+        // Manifest identifiers must be XML names, so they aren't made from
+        // the paths.
+        let ids: BTreeMap<&str, String> = files
+            .keys()
+            .filter(|a| *a != "nav.xhtml")
+            .enumerate()
+            .map(|(i, path)| (path.as_str(), format!("item{i}")))
+            .collect();
+        for (path, id) in &ids {
+            let mut properties = Vec::new();
+            let media_type = match path.rsplit_once('.') {
+                Some((_, "xhtml")) => {
+                    let content = String::from_utf8_lossy(&files[*path]);
+                    if content.contains("<math") {
+                        properties.push("mathml");
+                    }
+                    if regex!(r#"\bsrc="(https?:)?//"#).is_match(&content) {
+                        properties.push("remote-resources");
+                    }
+                    "application/xhtml+xml"
+                }
+                _ => mime_type(path).split(';').next().unwrap(),
+            };
+            let properties = if properties.is_empty() {
+                String::new()
+            } else {
+                format!(" properties=\"{}\"", properties.join(" "))
+            };
+            let _ = writeln!(
+                ret,
+                "<item id=\"{id}\" href=\"{}\" media-type=\"{media_type}\"{properties}/>",
+                escape(path)
+            );
+        }
+        ret.push_str("</manifest>\n<spine>\n<itemref idref=\"nav\"/>\n");
+        for post in posts {
+            let id = &ids[format!("{}.xhtml", post.slug).as_str()];
+            let _ = writeln!(ret, "<itemref idref=\"{id}\"/>");
+        }
+        ret.push_str("</spine>\n</package>\n");
+        ret
+    }
+}
+
+fn is_relative(url: &str) -> bool {
+    !url.starts_with('#')
+        && !url.starts_with("//")
+        && !regex!(r"^[a-zA-Z][a-zA-Z0-9+.-]*:").is_match(url)
+}
+
+fn xhtml_head(lang: &str, title: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" xml:lang="{0}" lang="{0}">
+<head>
+<title>{1}</title>
+<link rel="stylesheet" type="text/css" href="style.css"/>
+</head>
+<body>
+"#,
+        escape(lang),
+        escape(title)
+    )
+}
+
+const XHTML_NS: &str = "http://www.w3.org/1999/xhtml";
+
+/// Elements that have no end tag in HTML.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Parse HTML and write it out as well-formed XHTML.
+///
+/// The `href` and `src` attributes are passed through `url` with the
+/// attribute name. Numeric ids like the ones of footnotes aren't valid XML
+/// names and get a prefix, and attributes whose names aren't XML names are
+/// dropped.
+fn to_xhtml(html: &str, mut url: impl FnMut(&str, &str) -> String) -> String {
+    let mut ret = String::new();
+    let html = Html::parse_fragment(html);
+    for node in html.root_element().children() {
+        write_xhtml(&mut ret, node, XHTML_NS, &mut url);
+    }
+    ret
+}
+
+fn write_xhtml(
+    out: &mut String,
+    node: NodeRef<Node>,
+    parent_ns: &str,
+    url: &mut impl FnMut(&str, &str) -> String,
+) {
+    let e = match node.value() {
+        Node::Text(text) => {
+            out.push_str(&escape(text));
+            return;
+        }
+        Node::Element(e) => e,
+        // Comments and the rest are left out.
+        _ => return,
+    };
+    let name = e.name();
+    let _ = write!(out, "<{name}");
+    // MathML and SVG elements carry their namespace.
+    let ns = &*e.name.ns;
+    if ns != parent_ns {
+        let _ = write!(out, " xmlns=\"{}\"", escape(ns));
+    }
+    for (attr, value) in e.attrs() {
+        if attr == "xmlns" || !regex!(r"^[A-Za-z_][A-Za-z0-9_.-]*$").is_match(attr) {
+            continue;
+        }
+        let value = match attr {
+            "id" => fix_id(value),
+            "href" => {
+                let url = url(attr, value);
+                // Only the ids in the book have been changed.
+                match url.split_once('#') {
+                    Some((path, id)) if path.is_empty() || is_chapter(path) => {
+                        format!("{path}#{}", fix_id(id))
+                    }
+                    _ => url,
+                }
+            }
+            "src" => url(attr, value),
+            // Boolean attributes are written out in full.
+            _ if value.is_empty() => attr.to_owned(),
+            _ => value.to_owned(),
+        };
+        let _ = write!(out, " {attr}=\"{}\"", escape(&value));
+    }
+    if ns == XHTML_NS && VOID_ELEMENTS.contains(&name) {
+        out.push_str("/>");
+        return;
+    }
+    out.push('>');
+    for child in node.children() {
+        write_xhtml(out, child, ns, url);
+    }
+    let _ = write!(out, "</{name}>");
+}
+
+/// Whether a link target is a chapter of the book.
+fn is_chapter(path: &str) -> bool {
+    path.ends_with(".xhtml") && is_relative(path)
+}
+
+fn fix_id(id: &str) -> String {
+    if id.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') {
+        format!("n{id}")
+    } else {
+        id.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn epub_book() {
+        let post = |slug: &str, date: &str, content: &str| Post {
+            slug: slug.into(),
+            title: slug.into(),
+            date: date.into(),
+            feed_date: util::normalize_date(date),
+            tags: vec!["essay".into()],
+            content: content.into(),
+            ..Default::default()
+        };
+        let mut site = Site {
+            base_url: "https://example.com/".into(),
+            ..Default::default()
+        };
+        for p in [
+            post(
+                "second",
+                "2001-01-01",
+                "<p><a href=\"first#part\">a</a><br><a href=\"#1\">1</a></p><p id=\"1\"></p>",
+            ),
+            post(
+                "first",
+                "2000-01-01",
+                "<p><a href=\"https://example.com/links\">b</a> <img src=\"feed.svg\"> \
+                 <a href=\"https://www.rfc-editor.org/rfc/rfc9110#2.1\">c</a></p>",
+            ),
+            post(
+                "raw",
+                "2002-01-01",
+                &crate::markdown::to_html(
+                    "<details open><summary>More&mdash;</summary>\n\n\
+                     - [x] done\n- [ ] <input disabled> not done\n\n\
+                     </details>\n\n<p class=x>unclosed &nbsp;<b>tags\n\n$x^2$\n",
+                    &Default::default(),
+                    false,
+                )
+                .unwrap(),
+            ),
+            post(
+                "org",
+                "2003-01-01",
+                &crate::org::to_html("* Heading\nText.[fn:1]\n\n[fn:1] Note.\n").unwrap(),
+            ),
+        ] {
+            site.posts.insert(p.slug.clone(), p);
+        }
+
+        let data = epub(&site, None).unwrap();
+        let mut zip = zip::ZipArchive::new(Cursor::new(data)).unwrap();
+        assert_eq!(zip.by_index(0).unwrap().name(), "mimetype");
+        let mut read = |name: &str| {
+            let mut ret = String::new();
+            zip.by_name(name).unwrap().read_to_string(&mut ret).unwrap();
+            ret
+        };
+        let second = read("OEBPS/second.xhtml");
+        assert!(
+            second.contains("<a href=\"first.xhtml#part\">a</a><br/><a href=\"#n1\">"),
+            "{second}"
+        );
+        assert!(second.contains("<p id=\"n1\">"), "{second}");
+        let first = read("OEBPS/first.xhtml");
+        assert!(
+            first.contains("href=\"https://example.com/links\""),
+            "{first}"
+        );
+        assert!(
+            first.contains("href=\"https://www.rfc-editor.org/rfc/rfc9110#2.1\""),
+            "{first}"
+        );
+        let raw = read("OEBPS/raw.xhtml");
+        assert!(raw.contains("<details open=\"open\">"), "{raw}");
+        assert!(raw.contains("<input disabled=\"disabled\"/>"), "{raw}");
+        assert!(raw.contains("More—"), "{raw}");
+        assert!(
+            raw.contains("<math xmlns=\"http://www.w3.org/1998/Math/MathML\""),
+            "{raw}"
+        );
+        for name in ["first", "second", "raw", "org", "nav"] {
+            check_xml(&read(&format!("OEBPS/{name}.xhtml")));
+        }
+        let package = read("OEBPS/content.opf");
+        assert!(package.contains("href=\"feed.svg\" media-type=\"image/svg+xml\""));
+        assert!(package.find("first.xhtml").unwrap() < package.find("second.xhtml").unwrap());
+
+        assert!(epub(&site, Some("poem")).is_err());
+    }
+
+    /// Panic if the text isn't well-formed XML without a DTD.
+    fn check_xml(text: &str) {
+        use quick_xml::events::Event;
+        let check_attributes = |e: &quick_xml::events::BytesStart| {
+            for attr in e.attributes() {
+                attr.unwrap()
+                    .normalized_value(quick_xml::XmlVersion::Explicit1_0)
+                    .unwrap();
+            }
+        };
+        let mut reader = quick_xml::Reader::from_str(text);
+        // The reader doesn't report elements left open at the end.
+        let mut depth = 0;
+        loop {
+            match reader.read_event() {
+                Ok(Event::Eof) => break,
+                Ok(Event::Start(e)) => {
+                    depth += 1;
+                    check_attributes(&e);
+                }
+                Ok(Event::Empty(e)) => check_attributes(&e),
+                Ok(Event::End(_)) => depth -= 1,
+                Ok(Event::GeneralRef(e)) => {
+                    let name = e.xml10_content();
+                    assert!(
+                        e.is_char_ref() || ["amp", "lt", "gt", "quot", "apos"].contains(&&*name),
+                        "Undefined entity `{name}` in\n{text}"
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => panic!("{e} in\n{text}"),
+            }
+        }
+        assert_eq!(depth, 0, "Unclosed elements in\n{text}");
+    }
+}
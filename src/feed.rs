@@ -1,9 +1,9 @@
 use askama::Template;
-use serde_with::SerializeDisplay;
+use serde::Serialize;
 
-use crate::{util, Item, Post};
+use crate::{output::Page, util, Item, Post};
 
-#[derive(Default, Debug, Template, SerializeDisplay)]
+#[derive(Default, Debug, Hash, Serialize, Template)]
 #[template(path = "feed.xml")]
 /// Atom feed.
 pub struct Feed {
@@ -15,6 +15,10 @@ pub struct Feed {
     entries: Vec<FeedEntry>,
 }
 
+impl Page for Feed {
+    const TEMPLATES: &'static [&'static str] = &["feed.xml"];
+}
+
 impl Feed {
     pub fn new<T: Into<FeedEntry>>(
         base_url: &str,
@@ -49,7 +53,7 @@ impl Feed {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Hash, Serialize)]
 pub struct FeedEntry {
     pub title: String,
     pub link: String,
@@ -0,0 +1,238 @@
+//! External commands that convert post formats the engine doesn't know.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::output;
+
+/// How long a filter command may run before it's stopped.
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Output of a successful filter run.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Filtered {
+    /// Converted text from standard output.
+    pub html: String,
+    /// Messages the command wrote to standard error.
+    pub stderr: String,
+}
+
+/// Suffix of the filter result cache file next to the output directory.
+///
+/// The cache has the output of draft posts too, so it must not end up in
+/// the published site.
+pub const CACHE_SUFFIX: &str = "filter-cache";
+
+/// Results of filter runs by a digest of their inputs, so that unchanged
+/// posts don't run their commands again.
+#[derive(Default, Debug)]
+pub struct Cache {
+    /// Results from the previous build.
+    old: BTreeMap<String, Filtered>,
+    /// Results used in this build, only these are kept for the next one.
+    used: BTreeMap<String, Filtered>,
+}
+
+impl Cache {
+    /// Read the cache saved for an output directory.
+    ///
+    /// A missing or unreadable cache is treated as empty.
+    pub fn load(output: &Path) -> Self {
+        let old = output::sibling(output, CACHE_SUFFIX)
+            .ok()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|a| serde_json::from_str(&a).ok())
+            .unwrap_or_default();
+        Cache {
+            old,
+            used: Default::default(),
+        }
+    }
+
+    /// Save the results used in this build for an output directory.
+    pub fn save(&self, output: &Path) -> Result<()> {
+        let path = output::sibling(output, CACHE_SUFFIX)?;
+        fs::write(&path, serde_json::to_string(&self.used)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Run a text through a filter command line.
+    ///
+    /// The command line is run with the system shell, so it can use quoting
+    /// and pipes. The text is written to the command's standard input and
+    /// the converted text is read from its standard output.
+    pub fn run(&mut self, command: &str, text: &str) -> Result<Filtered> {
+        let key = key(command, text);
+        let ret = match self.used.get(&key).or_else(|| self.old.get(&key)) {
+            Some(ret) => ret.clone(),
+            None => run_uncached(command, text, TIMEOUT)?,
+        };
+        self.used.insert(key, ret.clone());
+        Ok(ret)
+    }
+}
+
+/// Digest of the inputs of a filter run.
+///
+/// The files the command line names, like the program or a script it
+/// runs, are included by size and modification time, so editing them runs
+/// the filter again.
+fn key(command: &str, text: &str) -> String {
+    let mut digest = md5::Context::new();
+    digest.consume(command);
+    for path in command_files(command) {
+        let Ok(meta) = fs::metadata(path) else {
+            continue;
+        };
+        digest.consume([0]);
+        digest.consume(meta.len().to_le_bytes());
+        if let Ok(time) = meta.modified().map(|a| a.duration_since(UNIX_EPOCH)) {
+            digest.consume(time.unwrap_or_default().as_nanos().to_le_bytes());
+        }
+    }
+    digest.consume([0]);
+    digest.consume(text);
+    format!("{:x}", digest.compute())
+}
+
+/// Existing files that the words of a command line name, with the program
+/// looked up from `PATH`.
+fn command_files(command: &str) -> Vec<PathBuf> {
+    let mut ret = Vec::new();
+    for (i, word) in command.split_whitespace().enumerate() {
+        let path = PathBuf::from(word.trim_matches(|c| c == '"' || c == '\''));
+        if path.is_file() {
+            ret.push(path);
+        } else if i == 0 {
+            if let Some(path) = std::env::var_os("PATH").and_then(|paths| {
+                std::env::split_paths(&paths)
+                    .map(|a| a.join(&path))
+                    .find(|a| a.is_file())
+            }) {
+                ret.push(path);
+            }
+        }
+    }
+    ret
+}
+
+fn run_uncached(command: &str, text: &str, timeout: Duration) -> Result<Filtered> {
+    if command.trim().is_empty() {
+        bail!("Empty filter command");
+    }
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run filter `{command}`"))?;
+
+    // Feed and drain the pipes in threads so that a command that fills one
+    // pipe before reading the other doesn't get stuck.
+    let mut stdin = child.stdin.take().unwrap();
+    let text = text.to_owned();
+    let writer = thread::spawn(move || stdin.write_all(text.as_bytes()));
+    let stdout = read_in_thread(child.stdout.take().unwrap());
+    let stderr = read_in_thread(child.stderr.take().unwrap());
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("Filter `{command}` timed out after {} s", timeout.as_secs());
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    // The command may exit without reading all of its input.
+    let _ = writer.join();
+    let html = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        bail!("Filter `{command}` failed with {status}: {}", stderr.trim());
+    }
+    Ok(Filtered {
+        html,
+        stderr: stderr.trim().to_owned(),
+    })
+}
+
+/// Command that runs a command line with the system shell.
+fn shell(command: &str) -> Command {
+    let mut ret;
+    if cfg!(windows) {
+        ret = Command::new("cmd");
+        ret.args(["/C", command]);
+    } else {
+        ret = Command::new("sh");
+        ret.args(["-c", command]);
+    }
+    ret
+}
+
+fn read_in_thread(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        String::from_utf8_lossy(&buf).into_owned()
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn filters() {
+        let out = run_uncached("tr a-z A-Z", "shout", TIMEOUT).unwrap();
+        assert_eq!(out.html, "SHOUT");
+        let out = run_uncached("cat; echo 'a warning' >&2", "text", TIMEOUT).unwrap();
+        assert_eq!(out.stderr, "a warning");
+
+        let err = run_uncached("echo oops >&2; false", "", TIMEOUT).unwrap_err();
+        assert!(err.to_string().ends_with(": oops"), "{err}");
+        let err = run_uncached("sleep 5", "", Duration::from_millis(100)).unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{err}");
+        assert!(run_uncached("no-such-command-here", "", TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn cache() {
+        let dir = TempDir::new("filters");
+        let script = dir.join("filter.sh");
+        fs::write(&script, "tr a-z A-Z").unwrap();
+        let command = format!("sh {}", script.display());
+
+        let mut cache = Cache::default();
+        assert_eq!(cache.run(&command, "text").unwrap().html, "TEXT");
+        cache.run("cat", "unused").unwrap();
+        let mut cache = Cache {
+            old: std::mem::take(&mut cache.used),
+            ..Default::default()
+        };
+        let key = key(&command, "text");
+        assert!(cache.old.contains_key(&key));
+        cache.run(&command, "text").unwrap();
+        assert_eq!(cache.used.keys().collect::<Vec<_>>(), vec![&key]);
+
+        // Editing the script runs the filter again.
+        fs::write(&script, "tr a-z A-Z; echo edited").unwrap();
+        assert_ne!(super::key(&command, "text"), key);
+        assert_eq!(cache.run(&command, "text").unwrap().html, "TEXTedited\n");
+    }
+}
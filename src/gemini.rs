@@ -0,0 +1,444 @@
+//! Gemtext versions of the site's posts and lists for a Gemini capsule.
+
+use std::fmt::Write;
+
+use anyhow::{Context, Result};
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, HeadingLevel, LinkType, Parser, Tag, TagEnd};
+use scraper::{node::Node, Html};
+
+use crate::{
+    input::{Format, MarkdownOptions},
+    outline::Outline,
+    Item, List, Post,
+};
+
+/// MIME type of gemtext files.
+pub const MIME_TYPE: &str = "text/gemini; charset=utf-8";
+
+/// Convert the body of a post into gemtext.
+///
+/// Markdown and outlines are converted from their source, other formats
+/// from the HTML they were rendered into.
+pub fn from_post(post: &Post) -> Result<String> {
+    match post.format {
+        Format::Markdown => Ok(from_markdown(&post.body, &post.markdown)),
+        Format::Outline => from_outline(&post.body, &post.markdown),
+        _ => Ok(from_html(&post.content)),
+    }
+}
+
+/// Convert markdown into gemtext.
+///
+/// Paragraphs are unwrapped into single lines and the links in a paragraph
+/// are listed as link lines after it.
+pub fn from_markdown(text: &str, options: &MarkdownOptions) -> String {
+    let mut w = Writer::default();
+    for event in Parser::new_ext(text, crate::markdown::parser_options(options)) {
+        w.event(event);
+    }
+    w.out.trim_end().to_owned() + "\n"
+}
+
+/// Convert an outline into list lines.
+///
+/// Gemtext lists don't nest, so child items are indented after the bullet.
+pub fn from_outline(text: &str, options: &MarkdownOptions) -> Result<String> {
+    let outline: Outline = idm::from_str(text).context("Failed to parse outline body")?;
+    let mut out = String::new();
+    push_outline(&mut out, &outline, 0, options);
+    Ok(out)
+}
+
+/// Convert HTML into gemtext.
+///
+/// The elements are read as the markdown events they would come from, so
+/// the conversion matches the markdown one.
+pub fn from_html(html: &str) -> String {
+    let mut w = Writer::default();
+    push_html(&mut w, Html::parse_fragment(html).root_element().children());
+    w.flush("", true);
+    w.out.trim_end().to_owned() + "\n"
+}
+
+fn push_html<'a>(w: &mut Writer, nodes: impl Iterator<Item = ego_tree::NodeRef<'a, Node>>) {
+    for node in nodes {
+        let e = match node.value() {
+            Node::Text(text) if w.in_code => {
+                w.event(Event::Text(CowStr::from(text.to_string())));
+                continue;
+            }
+            Node::Text(text) => {
+                // Runs of whitespace, line breaks included, are single
+                // spaces, like soft breaks in markdown.
+                let mut words = String::new();
+                if text.starts_with(char::is_whitespace) && !w.line.ends_with(' ') {
+                    words.push(' ');
+                }
+                words.push_str(&text.split_whitespace().collect::<Vec<_>>().join(" "));
+                if text.ends_with(char::is_whitespace) && !words.ends_with(' ') {
+                    words.push(' ');
+                }
+                w.event(Event::Text(words.into()));
+                continue;
+            }
+            Node::Element(e) => e,
+            _ => continue,
+        };
+        let attr = |name| CowStr::from(e.attr(name).unwrap_or_default().to_owned());
+        let tag = match e.name() {
+            "p" | "summary" => Tag::Paragraph,
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => Tag::Heading {
+                level: HeadingLevel::try_from(e.name()[1..].parse::<usize>().unwrap()).unwrap(),
+                id: None,
+                classes: Vec::new(),
+                attrs: Vec::new(),
+            },
+            "ul" => Tag::List(None),
+            "ol" => Tag::List(Some(1)),
+            "li" => Tag::Item,
+            "blockquote" => Tag::BlockQuote(None),
+            "pre" => {
+                // Highlighted code has its language on the code element.
+                let lang = node
+                    .descendants()
+                    .filter_map(|a| a.value().as_element())
+                    .flat_map(|a| a.classes())
+                    .find_map(|a| a.strip_prefix("language-"))
+                    .unwrap_or_default();
+                Tag::CodeBlock(CodeBlockKind::Fenced(lang.to_owned().into()))
+            }
+            "a" if e.attr("href").is_some() => Tag::Link {
+                link_type: LinkType::Inline,
+                dest_url: attr("href"),
+                title: "".into(),
+                id: "".into(),
+            },
+            "img" => {
+                w.event(Event::Start(Tag::Image {
+                    link_type: LinkType::Inline,
+                    dest_url: attr("src"),
+                    title: "".into(),
+                    id: "".into(),
+                }));
+                w.event(Event::Text(attr("alt")));
+                w.event(Event::End(TagEnd::Image));
+                continue;
+            }
+            "table" => Tag::Table(Vec::new()),
+            "tr" => Tag::TableRow,
+            "td" | "th" => Tag::TableCell,
+            "br" => {
+                w.event(Event::HardBreak);
+                continue;
+            }
+            "hr" => {
+                w.event(Event::Rule);
+                continue;
+            }
+            "input" if e.attr("type") == Some("checkbox") => {
+                w.event(Event::TaskListMarker(e.attr("checked").is_some()));
+                continue;
+            }
+            "sup" => {
+                // Footnote numbers in brackets, with a space before the note
+                // in definitions.
+                w.event(Event::Text("[".into()));
+                push_html(w, node.children());
+                let end = if e.has_class(
+                    "footnote-definition-label",
+                    scraper::CaseSensitivity::CaseSensitive,
+                ) {
+                    "] "
+                } else {
+                    "]"
+                };
+                w.event(Event::Text(end.into()));
+                continue;
+            }
+            "script" | "style" => continue,
+            name => {
+                push_html(w, node.children());
+                // Text in other block elements is a paragraph of its own.
+                if matches!(name, "div" | "section" | "details" | "figure")
+                    && !w.line.trim().is_empty()
+                {
+                    w.flush("", true);
+                }
+                continue;
+            }
+        };
+        let end = tag.to_end();
+        w.event(Event::Start(tag));
+        push_html(w, node.children());
+        w.event(Event::End(end));
+    }
+}
+
+fn push_outline(out: &mut String, outline: &Outline, depth: usize, options: &MarkdownOptions) {
+    for ((head,), ((_,), body)) in &outline.0 {
+        let mut w = Writer::default();
+        for event in Parser::new_ext(head, crate::markdown::parser_options(options)) {
+            match event {
+                // Headlines are inline text, don't let them turn into blocks.
+                Event::Start(Tag::Paragraph) | Event::End(TagEnd::Paragraph) => {}
+                e => w.event(e),
+            }
+        }
+        let _ = writeln!(out, "* {}{}", "  ".repeat(depth), w.line.trim());
+        w.push_links(out);
+        push_outline(out, body, depth + 1, options);
+    }
+}
+
+/// Gemtext page for a post.
+pub fn post(post: &Post) -> Result<String> {
+    let mut ret = format!("# {}\n\n", post.title);
+    let mut meta = post.date.clone();
+    if !post.tags.is_empty() {
+        if !meta.is_empty() {
+            meta.push_str(" · ");
+        }
+        meta.push_str(&post.tags.join(", "));
+    }
+    if !meta.is_empty() {
+        let _ = writeln!(ret, "{meta}\n");
+    }
+    ret.push_str(&from_post(post)?);
+    Ok(ret)
+}
+
+/// Gemtext page for a list of posts or links, with a link to its feed if
+/// `feed_path` is not empty.
+pub fn list(list: &List, feed_path: &str) -> String {
+    let mut ret = format!("# {}\n\n", list.title);
+    if !feed_path.is_empty() {
+        let _ = writeln!(ret, "=> {feed_path} Atom feed\n");
+    }
+    for item in &list.items {
+        ret.push_str(&self::item(item));
+    }
+    ret
+}
+
+fn item(item: &Item) -> String {
+    let url = if item.is_external() {
+        item.url.clone()
+    } else {
+        local_url(&item.url)
+    };
+    let mut ret = if item.date.is_empty() {
+        format!("=> {url} {}\n", item.title)
+    } else {
+        format!("=> {url} {} {}\n", item.date, item.title)
+    };
+    if !item.site.is_empty() || !item.tags.is_empty() {
+        let mut meta = item.site.clone();
+        if !item.tags.is_empty() {
+            if !meta.is_empty() {
+                meta.push_str(" · ");
+            }
+            meta.push_str(&item.tags.join(", "));
+        }
+        let _ = writeln!(ret, "{meta}");
+    }
+    ret
+}
+
+/// Point relative links to pages of the site at their gemtext files.
+fn local_url(url: &str) -> String {
+    let (path, anchor) = match url.find('#') {
+        Some(i) => url.split_at(i),
+        None => (url, ""),
+    };
+    let name = path.rsplit('/').next().unwrap_or(path);
+    if path.contains(':') || path.starts_with("//") || name.is_empty() || name.contains('.') {
+        url.to_owned()
+    } else {
+        format!("{path}.gmi{anchor}")
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    out: String,
+    /// Text of the current line.
+    line: String,
+    /// Links of the current block as URL and text pairs.
+    links: Vec<(String, String)>,
+    /// Link or image being read and its text so far.
+    link: Option<(String, String)>,
+    is_image: bool,
+    quote_depth: usize,
+    list_depth: usize,
+    in_code: bool,
+    table: Vec<String>,
+}
+
+impl Writer {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(lang) => lang.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.in_code = true;
+                let _ = writeln!(self.out, "```{lang}");
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                self.in_code = false;
+                let _ = writeln!(self.out, "```\n");
+            }
+            Event::Text(text) if self.in_code => self.out.push_str(&text),
+
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                self.link = Some((local_url(&dest_url), String::new()));
+            }
+            Event::Start(Tag::Image { dest_url, .. }) => {
+                self.link = Some((dest_url.to_string(), String::new()));
+                self.is_image = true;
+            }
+            Event::End(TagEnd::Link | TagEnd::Image) => {
+                // Links within the page, like footnote references, don't
+                // lead anywhere in gemtext.
+                if let Some(link) = self.link.take().filter(|(url, _)| !url.starts_with('#')) {
+                    self.links.push(link);
+                }
+                self.is_image = false;
+            }
+
+            Event::Start(Tag::BlockQuote(_)) => self.quote_depth += 1,
+            Event::End(TagEnd::BlockQuote(_)) => self.quote_depth -= 1,
+            Event::Start(Tag::List(_)) => {
+                // Flush the text of the parent item before a nested list.
+                if self.list_depth > 0 {
+                    self.flush("* ", false);
+                }
+                self.list_depth += 1;
+            }
+            Event::End(TagEnd::List(_)) => {
+                self.list_depth -= 1;
+                if self.list_depth == 0 {
+                    self.out.push('\n');
+                }
+            }
+            Event::End(TagEnd::Item) => self.flush("* ", false),
+            Event::End(TagEnd::Paragraph) if self.list_depth > 0 => self.line.push(' '),
+            Event::End(TagEnd::Paragraph | TagEnd::FootnoteDefinition) => self.flush("", true),
+            Event::End(TagEnd::Heading(level)) => {
+                let marks = "#".repeat((level as usize).min(3));
+                self.flush(&format!("{marks} "), true);
+            }
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                let _ = write!(self.line, "[{label}] ");
+            }
+
+            Event::End(TagEnd::TableCell) => self.line.push_str(" | "),
+            Event::End(TagEnd::TableHead | TagEnd::TableRow) => {
+                let row = std::mem::take(&mut self.line);
+                self.table.push(row.trim_end_matches(" | ").to_owned());
+            }
+            Event::End(TagEnd::Table) => {
+                let _ = writeln!(self.out, "```\n{}\n```\n", self.table.join("\n"));
+                self.table.clear();
+            }
+
+            Event::Text(text)
+            | Event::Code(text)
+            | Event::InlineMath(text)
+            | Event::DisplayMath(text) => self.text(&text),
+            Event::SoftBreak | Event::HardBreak => self.text(" "),
+            Event::FootnoteReference(label) => self.text(&format!("[{label}]")),
+            Event::TaskListMarker(done) => self.text(if done { "[x] " } else { "[ ] " }),
+            Event::Rule => self.out.push_str("---\n\n"),
+            // Gemtext has no markup for the rest, only the text is kept.
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if let Some((_, link_text)) = &mut self.link {
+            link_text.push_str(text);
+        }
+        // Image alt texts only show up in the link line.
+        if !self.is_image {
+            self.line.push_str(text);
+        }
+    }
+
+    /// Write out the current line and the links in it.
+    fn flush(&mut self, marker: &str, blank_line: bool) {
+        let line = std::mem::take(&mut self.line);
+        let line = line.trim();
+        if !line.is_empty() {
+            let _ = writeln!(self.out, "{}{marker}{line}", "> ".repeat(self.quote_depth));
+        }
+        let mut out = std::mem::take(&mut self.out);
+        self.push_links(&mut out);
+        self.out = out;
+        if blank_line && self.list_depth == 0 {
+            self.out.push('\n');
+        }
+    }
+
+    fn push_links(&mut self, out: &mut String) {
+        for (url, text) in self.links.drain(..) {
+            let text = text.trim();
+            if text.is_empty() || text == url {
+                let _ = writeln!(out, "=> {url}");
+            } else {
+                let _ = writeln!(out, "=> {url} {text}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gemtext() {
+        let options = MarkdownOptions::default();
+        let text =
+            "# Title\n\nSome text\nwith a [link](other) and\n[another](https://example.com/).\n\n\
+                    - one\n- two\n  - nested\n\n```rust\nfn main() {}\n```\n";
+        assert_eq!(
+            from_markdown(text, &options),
+            "# Title\n\n\
+             Some text with a link and another.\n\
+             => other.gmi link\n\
+             => https://example.com/ another\n\n\
+             * one\n* two\n* nested\n\n\
+             ```rust\nfn main() {}\n```\n"
+        );
+
+        assert_eq!(
+            from_outline("Top [x](https://x.org/)\n  Child\n", &options).unwrap(),
+            "* Top x\n=> https://x.org/ x\n*   Child\n"
+        );
+
+        let html = crate::org::to_html(
+            "* Heading\nSome /text/\nwith a [[https://x.org/][link]].[fn:1]\n\n\
+             - [X] done\n\n#+BEGIN_SRC rust\nfn main() {}\n#+END_SRC\n\n[fn:1] A note.\n",
+        )
+        .unwrap();
+        assert_eq!(
+            from_html(&html),
+            "# Heading\n\n\
+             Some text with a link.[1]\n\
+             => https://x.org/ link\n\n\
+             * [X] done\n\n\
+             ```rust\nfn main() {}\n```\n\n\
+             [1] A note.\n"
+        );
+        assert_eq!(
+            from_html("<p>Text&mdash;<br>more</p>\n<div>Loose <em>text</em></div>"),
+            "Text— more\n\nLoose text\n"
+        );
+
+        assert_eq!(local_url("post#part"), "post.gmi#part");
+        assert_eq!(local_url("image.png"), "image.png");
+        assert_eq!(local_url("mailto:a@b.c"), "mailto:a@b.c");
+    }
+}
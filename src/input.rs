@@ -1,18 +1,88 @@
 //! Input types that match the IDM site.
 
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use anyhow::bail;
 use indexmap::IndexMap;
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_with::DeserializeFromStr;
+use url::Url;
 
-use crate::util::{Outline, Word};
+use crate::{
+    lint::{Level, Lint, Lints},
+    source::Source,
+    util::{self, Outline, Word},
+};
 
-#[derive(Copy, Clone, Default, Debug, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Clone, Default, PartialEq, Eq, Hash, Debug, DeserializeFromStr)]
 pub enum Format {
     /// Markdown-formatted post.
     #[default]
     Markdown,
     /// Indented lines outline formatted post.
     Outline,
+    /// Emacs Org-mode formatted post.
+    Org,
+    /// Djot-formatted post.
+    Djot,
+    /// Post converted by a filter command named in the settings.
+    External(String),
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    /// Parse a format name.
+    ///
+    /// Names other than the built-in formats are filter formats, except for
+    /// names a single typo away from a built-in format, which are errors.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim() {
+            "" => bail!("Empty format"),
+            "markdown" => Format::Markdown,
+            "outline" => Format::Outline,
+            "org" => Format::Org,
+            "djot" => Format::Djot,
+            name => {
+                if let Some(a) = Format::BUILT_IN
+                    .iter()
+                    .find(|a| strsim::damerau_levenshtein(name, a) <= 1)
+                {
+                    bail!("Unknown format `{name}`, did you mean `{a}`?");
+                }
+                Format::External(name.to_owned())
+            }
+        })
+    }
+}
+
+impl Format {
+    /// Names of the formats the engine converts itself.
+    pub const BUILT_IN: &'static [&'static str] = &["markdown", "outline", "org", "djot"];
+
+    /// Format of a post file with the given extension, if the extension
+    /// says.
+    pub fn from_extension(extension: &str) -> Option<Format> {
+        match extension {
+            "md" => Some(Format::Markdown),
+            "org" => Some(Format::Org),
+            "dj" => Some(Format::Djot),
+            _ => None,
+        }
+    }
+}
+
+/// Publication state of a post.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    /// Post is shown everywhere once its date has come.
+    #[default]
+    Published,
+    /// Post is only built in draft builds.
+    Draft,
+    /// Post is built but left out of lists and feeds.
+    Unlisted,
 }
 
 /// Main site structure, the entire `site/` subdirectory is deserialized into
@@ -22,6 +92,8 @@ pub enum Format {
 pub struct Site {
     /// Blog posts authored by the site owner.
     pub posts: IndexMap<String, ((PostHeader,), String)>,
+    /// Standalone pages like an About page that aren't dated posts.
+    pub pages: IndexMap<String, ((PageHeader,), String)>,
     /// Links to external sites.
     pub links: Vec<(String, ((LinkHeader,), String))>,
     /// A tree of topic tags that will be automatically added if a sub-tag is
@@ -40,7 +112,7 @@ pub struct Site {
     pub settings: Settings,
 }
 
-#[derive(Default, Debug, Deserialize)]
+#[derive(Clone, Default, Debug, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct PostHeader {
     /// Title of the post.
@@ -53,9 +125,43 @@ pub struct PostHeader {
     pub tags: Vec<Word>,
     /// Format of the post content.
     ///
-    /// Default is markdown, but other formats can be supported as well,
-    /// controls how the post content is turned into HTML.
-    pub format: Format,
+    /// Controls how the post content is turned into HTML. If not set, the
+    /// format is guessed from the post's file extension and is markdown
+    /// otherwise.
+    pub format: Option<Format>,
+    /// Publication state of the post.
+    pub status: Status,
+    /// Markdown extensions that differ from the site settings.
+    pub markdown: MarkdownOptions,
+    /// Whether to show a table of contents built from the headings.
+    pub toc: bool,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PageHeader {
+    /// Title of the page.
+    pub title: String,
+    /// Format of the page content.
+    pub format: Option<Format>,
+    /// Whether to show a link to the page in the site navigation.
+    pub nav: bool,
+    /// Markdown extensions that differ from the site settings.
+    pub markdown: MarkdownOptions,
+    /// Whether to show a table of contents built from the headings.
+    pub toc: bool,
+}
+
+impl From<&PageHeader> for PostHeader {
+    fn from(page: &PageHeader) -> Self {
+        PostHeader {
+            title: page.title.clone(),
+            format: page.format.clone(),
+            markdown: page.markdown,
+            toc: page.toc,
+            ..Default::default()
+        }
+    }
 }
 
 #[derive(Default, Debug, Deserialize)]
@@ -76,6 +182,8 @@ pub struct LinkHeader {
     pub tags: Vec<Word>,
     /// Subsequent URLs if the link refers to a multi-part series.
     pub sequence: Vec<String>,
+    /// Format of the link notes, markdown by default.
+    pub format: Format,
 }
 
 /// Site configuration.
@@ -86,6 +194,9 @@ pub struct Settings {
     pub site_name: String,
     /// Base URL the site is being deployed to.
     pub base_url: String,
+    /// Base URL of the Gemini capsule version of the site, like
+    /// `gemini://example.com/`.
+    pub gemini_url: String,
     /// Default author for posts.
     pub author: String,
     /// Bypass link for DOI articles, if any.
@@ -96,4 +207,269 @@ pub struct Settings {
     /// of the link) the generated URL would be
     /// `https://example.com/?doi=10.1112/plms/s2-42.1.230`
     pub doi_bypass: Option<String>,
+    /// Lint levels that override the defaults.
+    ///
+    /// ```notrust
+    /// :lints
+    ///   redundant-tags allow
+    ///   duplicate-link deny
+    /// ```
+    pub lints: BTreeMap<Lint, Level>,
+    /// Language of the site as a BCP 47 tag, `en` if not set.
+    pub lang: String,
+    /// Links in the site header as URL and title pairs.
+    ///
+    /// Defaults to the post and link lists. Pages with `:nav true` are added
+    /// after these.
+    ///
+    /// ```notrust
+    /// :nav
+    ///   . posts
+    ///   links bookmarks
+    /// ```
+    pub nav: Vec<(String, String)>,
+    /// Links in the page footer as URL and title pairs.
+    pub footer: Vec<(String, String)>,
+    /// License of the site content, shown in the page footer.
+    ///
+    /// ```notrust
+    /// :license
+    ///   :name CC BY-NC-SA 4.0
+    ///   :url https://creativecommons.org/licenses/by-nc-sa/4.0/
+    ///   :badge by-nc-sa.svg
+    /// ```
+    pub license: Option<License>,
+    /// Syntax highlighting theme for code blocks, `InspiredGitHub` if not
+    /// set.
+    pub highlight_theme: String,
+    /// Markdown extensions used by posts and link notes.
+    ///
+    /// ```notrust
+    /// :markdown
+    ///   :smart-punctuation true
+    ///   :footnotes false
+    /// ```
+    pub markdown: MarkdownOptions,
+    /// Commands for converting post formats the engine doesn't know, by
+    /// format name.
+    ///
+    /// ```notrust
+    /// :filters
+    ///   rst pandoc -f rst -t html
+    /// ```
+    pub filters: BTreeMap<String, String>,
+}
+
+/// Optional markdown syntax extensions.
+///
+/// Unset options fall back to the site settings and then to the defaults.
+/// Smart punctuation and sidenotes are off by default and the rest are on.
+#[derive(Copy, Clone, Default, Hash, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct MarkdownOptions {
+    pub tables: Option<bool>,
+    pub footnotes: Option<bool>,
+    pub strikethrough: Option<bool>,
+    pub task_lists: Option<bool>,
+    /// TeX math between `$` or `$$`, rendered as MathML.
+    pub math: Option<bool>,
+    /// Turn straight quotes, `--` and `...` into typographic ones.
+    pub smart_punctuation: Option<bool>,
+    /// Show footnotes as notes in the margin next to their references.
+    pub sidenotes: Option<bool>,
+    /// Pass HTML in the text through as is instead of escaping it.
+    pub raw_html: Option<bool>,
+}
+
+impl MarkdownOptions {
+    /// Fill in the unset options from another set of options.
+    pub fn or(self, other: MarkdownOptions) -> MarkdownOptions {
+        MarkdownOptions {
+            tables: self.tables.or(other.tables),
+            footnotes: self.footnotes.or(other.footnotes),
+            strikethrough: self.strikethrough.or(other.strikethrough),
+            task_lists: self.task_lists.or(other.task_lists),
+            math: self.math.or(other.math),
+            smart_punctuation: self.smart_punctuation.or(other.smart_punctuation),
+            sidenotes: self.sidenotes.or(other.sidenotes),
+            raw_html: self.raw_html.or(other.raw_html),
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug, Hash, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct License {
+    /// Name of the license.
+    pub name: String,
+    /// URL of the license text.
+    pub url: String,
+    /// Image shown instead of the license name, if any.
+    pub badge: Option<String>,
+}
+
+impl Settings {
+    /// Fail if a format is neither built in nor has a filter command.
+    pub fn check_format(&self, format: &Format) -> anyhow::Result<()> {
+        let Format::External(name) = format else {
+            return Ok(());
+        };
+        if self.filters.contains_key(name) {
+            return Ok(());
+        }
+        let mut known = Format::BUILT_IN.to_vec();
+        known.extend(self.filters.keys().map(|a| a.as_str()));
+        // Format names are often short, so single typos are matched too.
+        let close = known
+            .iter()
+            .find(|a| strsim::damerau_levenshtein(name, a) <= 1)
+            .copied();
+        let message = match close.or_else(|| util::did_you_mean(name, &known)) {
+            Some(a) => format!("Unknown format `{name}`, did you mean `{a}`?"),
+            None => format!(
+                "Unknown format `{name}`, add a command for it under `:filters` in the settings"
+            ),
+        };
+        Err(UnknownFormat(message).into())
+    }
+}
+
+/// Error for a format that is neither built in nor has a filter command.
+///
+/// Lets the error be reported at the `:format` line of the item.
+#[derive(Debug)]
+pub struct UnknownFormat(String);
+
+impl fmt::Display for UnknownFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFormat {}
+
+/// Check the site source for unknown fields and malformed values.
+///
+/// Serde skips fields it doesn't recognize, so a misspelled field name would
+/// otherwise silently lose its value.
+pub fn validate(source: &Source, lints: &mut Lints) {
+    for (section, i) in source.children(None) {
+        match section {
+            "posts" => {
+                for (_, j) in source.children(Some(i)) {
+                    check_fields::<PostHeader>(source, j, lints);
+                }
+            }
+            "pages" => {
+                for (_, j) in source.children(Some(i)) {
+                    check_fields::<PageHeader>(source, j, lints);
+                }
+            }
+            "links" => {
+                for (_, j) in source.children(Some(i)) {
+                    check_fields::<LinkHeader>(source, j, lints);
+                }
+            }
+            "settings" => check_fields::<Settings>(source, i, lints),
+            "tag-hierarchy" => {}
+            _ => lints.emit(
+                Lint::UnknownSection,
+                source,
+                Some(i),
+                unknown("section", section, util::field_names::<Site>()),
+            ),
+        }
+    }
+}
+
+fn check_fields<T: DeserializeOwned>(source: &Source, parent: usize, lints: &mut Lints) {
+    let fields = util::field_names::<T>();
+
+    for attr in source.attributes(Some(parent)) {
+        let line = Some(attr.line);
+        if !fields.contains(&attr.key) {
+            lints.emit(
+                Lint::UnknownField,
+                source,
+                line,
+                unknown("field", attr.key, fields),
+            );
+            continue;
+        }
+
+        let value = attr.value.as_str();
+        match attr.key {
+            // Nested blocks are checked against their own fields.
+            "markdown" => check_fields::<MarkdownOptions>(source, attr.line, lints),
+            "license" => check_fields::<License>(source, attr.line, lints),
+            "date" | "updated" | "added" if !value.is_empty() && !util::is_valid_date(value) => {
+                lints.emit(
+                    Lint::MalformedDate,
+                    source,
+                    line,
+                    format!("Malformed date `{value}`"),
+                );
+            }
+            "uri" | "mirror" | "base-url" | "sequence" => {
+                for uri in value.split_whitespace() {
+                    if let Err(e) = Url::parse(uri) {
+                        lints.emit(
+                            Lint::MalformedUri,
+                            source,
+                            line,
+                            format!("Malformed URI `{uri}`: {e}"),
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn unknown(kind: &str, name: &str, candidates: &[&str]) -> String {
+    match util::did_you_mean(name, candidates) {
+        Some(a) => format!("Unknown {kind} `{name}`, did you mean `{a}`?"),
+        None => format!("Unknown {kind} `{name}`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn unknown_fields() {
+        let source = test_util::source(&[
+            (
+                "posts/post.md",
+                ":title Post\n:tag rust\n:date 2000-13-01\n:markdown\n  :sidenote true\n  \
+                 :smart_punctuation true\n\n:not-a-field in body\n",
+            ),
+            (
+                "settings.idm",
+                ":markdown\n  :tables false\n:license\n  :nme CC0\n",
+            ),
+        ]);
+
+        let mut lints = Lints::default();
+        validate(&source, &mut lints);
+        let errors: Vec<String> = lints
+            .diagnostics
+            .iter()
+            .map(|e| format!("{}: {}", e.location.as_ref().unwrap(), e.message))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "site/posts/post.md:2: Unknown field `tag`, did you mean `tags`?",
+                "site/posts/post.md:3: Malformed date `2000-13-01`",
+                "site/posts/post.md:5: Unknown field `sidenote`, did you mean `sidenotes`?",
+                "site/posts/post.md:6: Unknown field `smart_punctuation`, \
+                 did you mean `smart-punctuation`?",
+                "site/settings.idm:4: Unknown field `nme`, did you mean `name`?",
+            ]
+        );
+    }
 }
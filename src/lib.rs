@@ -0,0 +1,130 @@
+//! IDM-based blog generator.
+//!
+//! The site is read from a source directory into a [`source::Source`],
+//! parsed into [`input::Site`], turned into the output [`Site`] and written
+//! into a directory of static web pages.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+
+pub mod cache;
+pub mod djot;
+pub mod epub;
+mod feed;
+pub mod filter;
+pub mod gemini;
+pub mod input;
+pub mod lint;
+mod list;
+pub mod markdown;
+pub mod org;
+pub mod outline;
+pub mod output;
+pub mod serve;
+mod site;
+pub mod source;
+#[cfg(test)]
+mod test_util;
+pub mod theme;
+pub mod util;
+
+pub use feed::Feed;
+pub use list::{Item, List};
+pub use site::{Chrome, NavLink, Post, Site};
+
+pub const FEED_LINK_COUNT: usize = 10;
+
+/// Options for loading a site.
+#[derive(Clone, Default, Debug)]
+pub struct Options {
+    /// Skip broken posts, pages and links instead of stopping at the first
+    /// error.
+    pub keep_going: bool,
+    /// Treat lint warnings as errors.
+    pub strict: bool,
+    /// Include draft posts and posts dated in the future.
+    pub drafts: bool,
+    /// Output directory of an earlier build whose filter command results
+    /// are reused.
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// A site loaded from source along with the problems found in it.
+#[derive(Debug)]
+pub struct Loaded {
+    pub site: Site,
+    /// Errors from posts and links skipped in keep-going mode.
+    pub errors: util::ErrorLog,
+    pub lints: lint::Lints,
+}
+
+/// Read, parse and lint the site under a source directory.
+pub fn load(path: impl AsRef<Path>, options: &Options) -> Result<Loaded> {
+    let path = path.as_ref();
+    let source = source::Source::read(path).with_context(|| "Failed to read site data")?;
+    let mut errors = util::ErrorLog::new(options.keep_going);
+
+    let site: input::Site = if options.keep_going {
+        let (site, skipped) = source
+            .parse_skipping(&["posts", "pages", "links"])
+            .with_context(|| "Failed to parse site data")?;
+        for e in skipped {
+            errors.push(e)?;
+        }
+        site
+    } else {
+        source
+            .parse()
+            .with_context(|| "Failed to parse site data")?
+    };
+
+    let mut lints = lint::Lints::new(&site.settings.lints, options.strict);
+    input::validate(&source, &mut lints);
+    let mut site = Site::new(&site, &source, options, &mut errors, &mut lints)?;
+    site.templates = theme::Templates::load(path.join("templates"))
+        .with_context(|| "Failed to load site templates")?
+        .map(Arc::new);
+    site.static_dir = Some(static_dir(path));
+
+    Ok(Loaded {
+        site,
+        errors,
+        lints,
+    })
+}
+
+/// Directory of the site's own static files, next to the source directory.
+pub fn static_dir(source: impl AsRef<Path>) -> PathBuf {
+    let source = source.as_ref();
+    match source.parent() {
+        Some(parent) => parent.join("static"),
+        None => source.join("static"),
+    }
+}
+
+/// Write the site and the static files into an output directory.
+///
+/// Only files whose inputs have changed since the last write are written,
+/// and files that are no longer part of the site are removed.
+pub fn write(site: &Site, output: impl AsRef<Path>) -> Result<()> {
+    let output = output.as_ref();
+    site.output()?.write(output)?;
+    site.filters.save(output)
+}
+
+/// Write the published posts of the site, or the ones with a tag, into an
+/// EPUB file.
+pub fn write_epub(site: &Site, tag: Option<&str>, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    std::fs::write(path, epub::epub(site, tag)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Write the Gemini capsule version of the site into an output directory.
+pub fn write_gemini(site: &Site, output: impl AsRef<Path>) -> Result<()> {
+    site.gemini_output()?.write(output)
+}
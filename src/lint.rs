@@ -0,0 +1,231 @@
+//! Named lints with configurable severities.
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::source::{Location, Source, SourceError};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Lint {
+    /// Top-level site section that isn't part of the site schema.
+    UnknownSection,
+    /// Header field that isn't part of the schema, probably a typo.
+    UnknownField,
+    /// Date that isn't a full or partial ISO 8601 date.
+    MalformedDate,
+    /// URI field that doesn't parse as an URI.
+    MalformedUri,
+    /// The same URL is in the links list more than once.
+    DuplicateLink,
+    /// Tags that are implied by other tags via the tag hierarchy.
+    RedundantTags,
+    /// A format filter command wrote messages to its standard error.
+    FilterOutput,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Lint::UnknownSection => "unknown-section",
+            Lint::UnknownField => "unknown-field",
+            Lint::MalformedDate => "malformed-date",
+            Lint::MalformedUri => "malformed-uri",
+            Lint::DuplicateLink => "duplicate-link",
+            Lint::RedundantTags => "redundant-tags",
+            Lint::FilterOutput => "filter-output",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Copy, Clone, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Level {
+    /// Do not report the lint.
+    Allow,
+    /// Report the lint but keep building.
+    #[default]
+    Warn,
+    /// Report the lint as an error.
+    Deny,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Allow => write!(f, "allow"),
+            Level::Warn => write!(f, "warning"),
+            Level::Deny => write!(f, "error"),
+        }
+    }
+}
+
+/// A reported lint or error.
+#[derive(Clone, Debug, Serialize)]
+pub struct Diagnostic {
+    /// The lint that was reported, `None` for errors that aren't lints.
+    pub lint: Option<Lint>,
+    pub level: Level,
+    #[serde(serialize_with = "serialize_location")]
+    pub location: Option<Location>,
+    pub message: String,
+    /// Text of the offending source line, if known.
+    pub snippet: Option<String>,
+    /// Numbered source file lines around the offending line.
+    #[serde(skip)]
+    pub context: Vec<(usize, String)>,
+}
+
+impl Diagnostic {
+    /// Line number of the diagnostic in its source file, if known.
+    pub fn line(&self) -> Option<usize> {
+        self.location.as_ref().and_then(|a| a.line)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.lint {
+            Some(lint) => write!(f, "{}[{lint}]: ", self.level)?,
+            None => write!(f, "{}: ", self.level)?,
+        }
+        if let Some(loc) = &self.location {
+            write!(f, "{loc}: ")?;
+        }
+        write!(f, "{}", self.message)?;
+        if let (Some(line), Some(snippet)) = (self.line(), &self.snippet) {
+            write!(f, "\n{line:>5} | {snippet}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&anyhow::Error> for Diagnostic {
+    fn from(e: &anyhow::Error) -> Self {
+        match e.downcast_ref::<SourceError>() {
+            Some(e) => Diagnostic {
+                lint: None,
+                level: Level::Deny,
+                location: Some(e.location.clone()),
+                message: e.message.clone(),
+                snippet: e.snippet.clone(),
+                context: e.context.clone(),
+            },
+            None => Diagnostic {
+                lint: None,
+                level: Level::Deny,
+                location: None,
+                message: format!("{e:#}"),
+                snippet: None,
+                context: Vec::new(),
+            },
+        }
+    }
+}
+
+fn serialize_location<S: serde::Serializer>(
+    location: &Option<Location>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Loc<'a> {
+        file: std::borrow::Cow<'a, str>,
+        line: Option<usize>,
+    }
+
+    location
+        .as_ref()
+        .map(|a| Loc {
+            file: a.path.to_string_lossy(),
+            line: a.line,
+        })
+        .serialize(serializer)
+}
+
+/// Lint levels and the diagnostics reported so far.
+#[derive(Default, Debug)]
+pub struct Lints {
+    levels: BTreeMap<Lint, Level>,
+    /// Report warnings as errors.
+    strict: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Lints {
+    pub fn new(levels: &BTreeMap<Lint, Level>, strict: bool) -> Self {
+        Lints {
+            levels: levels.clone(),
+            strict,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        match self.levels.get(&lint).copied().unwrap_or_default() {
+            Level::Warn if self.strict => Level::Deny,
+            level => level,
+        }
+    }
+
+    /// Report a lint at a line of the merged source text.
+    pub fn emit(
+        &mut self,
+        lint: Lint,
+        source: &Source,
+        line_idx: Option<usize>,
+        message: impl fmt::Display,
+    ) {
+        let level = self.level(lint);
+        if level == Level::Allow {
+            return;
+        }
+
+        let location = line_idx.and_then(|i| source.location(i));
+        self.diagnostics.push(Diagnostic {
+            lint: Some(lint),
+            level,
+            snippet: location
+                .as_ref()
+                .and_then(|a| source.snippet(a))
+                .map(String::from),
+            context: location
+                .as_ref()
+                .map(|a| source.context(a))
+                .unwrap_or_default(),
+            location,
+            message: message.to_string(),
+        });
+    }
+
+    /// Number of diagnostics reported as errors.
+    pub fn error_count(&self) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|a| a.level == Level::Deny)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let config = BTreeMap::from([
+            (Lint::RedundantTags, Level::Allow),
+            (Lint::DuplicateLink, Level::Deny),
+        ]);
+
+        let lints = Lints::new(&config, false);
+        assert_eq!(lints.level(Lint::RedundantTags), Level::Allow);
+        assert_eq!(lints.level(Lint::DuplicateLink), Level::Deny);
+        assert_eq!(lints.level(Lint::UnknownField), Level::Warn);
+
+        let lints = Lints::new(&config, true);
+        assert_eq!(lints.level(Lint::RedundantTags), Level::Allow);
+        assert_eq!(lints.level(Lint::UnknownField), Level::Deny);
+    }
+}
@@ -1,14 +1,19 @@
+use std::sync::Arc;
+
+use anyhow::Result;
 use askama::Template;
-use serde_with::SerializeDisplay;
+use serde::Serialize;
 use url::Url;
 
 use crate::{
-    input,
+    filter, input,
+    output::Page,
+    site::Chrome,
     util::{self, Tag},
     Post,
 };
 
-#[derive(Default, Debug, Template, SerializeDisplay)]
+#[derive(Default, Debug, Hash, Serialize, Template)]
 #[template(path = "list.html")]
 pub struct List {
     pub title: String,
@@ -18,6 +23,11 @@ pub struct List {
     pub items: Vec<Item>,
     /// Tag cloud.
     pub tags: Vec<Tag>,
+    pub chrome: Arc<Chrome>,
+}
+
+impl Page for List {
+    const TEMPLATES: &'static [&'static str] = &["list.html", "base.html"];
 }
 
 impl List {
@@ -38,11 +48,12 @@ impl List {
             feed_path: feed_path.into(),
             items,
             tags,
+            chrome: Default::default(),
         }
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Hash, Serialize)]
 pub struct Item {
     /// URL to local site's bookmark list.
     pub home_url: String,
@@ -84,6 +95,10 @@ pub struct Item {
 
     /// Local anchor ID hashed from URL.
     pub id: String,
+
+    /// Messages from rendering the notes that are reported as lints.
+    #[serde(skip)]
+    pub warnings: Vec<String>,
 }
 
 impl Item {
@@ -92,12 +107,14 @@ impl Item {
         title: &str,
         data: &input::LinkHeader,
         content: &str,
-    ) -> Self {
+        filters: &mut filter::Cache,
+    ) -> Result<Self> {
         let mut title = title.to_owned();
+        let mut warnings = Vec::new();
 
         // Mark PDF links
-        let file_looks_like_pdf = data.uri.ends_with(".pdf")
-            || data.mirror.as_ref().map_or(false, |a| a.ends_with(".pdf"));
+        let file_looks_like_pdf =
+            data.uri.ends_with(".pdf") || data.mirror.as_ref().is_some_and(|a| a.ends_with(".pdf"));
         if file_looks_like_pdf && (!title.ends_with(".pdf") && !title.ends_with(" (pdf)")) {
             title.push_str(" (pdf)");
         }
@@ -128,7 +145,7 @@ impl Item {
 
         let id = base64_url::encode(&md5::compute(&canonical_url).0);
 
-        Item {
+        Ok(Item {
             home_url: format!("{}links#{}", settings.base_url, id),
             url,
             site,
@@ -145,13 +162,18 @@ impl Item {
             },
             tags: data.tags.iter().cloned().map(String::from).collect(),
             sequence: data.sequence.clone(),
-            preview: {
-                let mut html = String::new();
-                pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(content));
-                html
-            },
+            preview: crate::site::render(
+                &data.format,
+                content,
+                settings,
+                &settings.markdown,
+                false,
+                filters,
+                &mut warnings,
+            )?,
             id,
-        }
+            warnings,
+        })
     }
 
     pub fn new_post(post: &Post) -> Self {
@@ -1,37 +1,317 @@
-use clap::Parser;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-mod feed;
-mod input;
-mod list;
-mod site;
-mod util;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 
-use anyhow::{Context, Result};
-pub use feed::Feed;
-pub use list::{Item, List};
-pub use site::{Post, Site};
-
-pub const FEED_LINK_COUNT: usize = 10;
+use blog_engine::{
+    lint,
+    serve::{self, LiveReload},
+    util, Options, Site,
+};
 
 #[derive(Parser, Debug)]
 struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Build the site into a static website.
+    Build {
+        #[command(flatten)]
+        site: SiteArgs,
+
+        /// Path for generated HTML site.
+        #[clap(long, value_name = "PATH", default_value = "./public_html/")]
+        output: PathBuf,
+
+        /// Also write a Gemini capsule of the site into this path.
+        #[clap(long, value_name = "PATH")]
+        gemini: Option<PathBuf>,
+    },
+
+    /// Check the site for errors and lints without writing anything.
+    Check {
+        #[command(flatten)]
+        site: SiteArgs,
+
+        /// Diagnostic output format.
+        #[clap(long, value_enum, default_value = "human")]
+        format: DiagnosticFormat,
+    },
+
+    /// Serve the site on a local web server and rebuild it when it changes.
+    Serve {
+        #[command(flatten)]
+        site: SiteArgs,
+
+        /// Path for generated HTML site.
+        #[clap(long, value_name = "PATH", default_value = "./public_html/")]
+        output: PathBuf,
+
+        /// Address to serve the site at.
+        #[clap(long, default_value = "localhost:8080")]
+        address: String,
+    },
+
+    /// Export the site into another format.
+    Export {
+        #[command(subcommand)]
+        format: Export,
+    },
+
+    /// Create a new post.
+    New {
+        /// Path of site source.
+        #[clap(long, value_name = "PATH", default_value = "./site/")]
+        source: PathBuf,
+
+        /// Title of the post.
+        title: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum Export {
+    /// Bundle the published posts into an EPUB book.
+    Epub {
+        #[command(flatten)]
+        site: SiteArgs,
+
+        /// Only include posts with this tag.
+        #[clap(long)]
+        tag: Option<String>,
+
+        /// Path of the EPUB file.
+        #[clap(long, value_name = "PATH", default_value = "./site.epub")]
+        output: PathBuf,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct SiteArgs {
     /// Path of site source.
     #[clap(long, value_name = "PATH", default_value = "./site/")]
-    source: std::path::PathBuf,
+    source: PathBuf,
+
+    /// Skip broken posts, pages and links instead of stopping at the first
+    /// error.
+    ///
+    /// The build still exits with an error status if anything was skipped.
+    #[clap(long)]
+    keep_going: bool,
+
+    /// Treat lint warnings as errors.
+    #[clap(long)]
+    strict: bool,
+
+    /// Include draft posts and posts dated in the future.
+    #[clap(long)]
+    drafts: bool,
+}
 
-    /// Path for generated HTML site.
-    #[clap(long, value_name = "PATH", default_value = "./public_html/")]
-    output: std::path::PathBuf,
+impl SiteArgs {
+    fn options(&self) -> Options {
+        Options {
+            keep_going: self.keep_going,
+            strict: self.strict,
+            drafts: self.drafts,
+            cache_dir: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum DiagnosticFormat {
+    /// Human-readable messages.
+    Human,
+    /// One JSON object per line.
+    Json,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    let site_text =
-        util::read_directory(&args.source).with_context(|| "Failed to read site data")?;
-    let site: Site = idm::from_str(&site_text).with_context(|| "Failed to parse site data")?;
-    util::write_directory(&args.output, &site).with_context(|| "Failed to write site web page")?;
-    dircpy::copy_dir("static/", &args.output).with_context(|| "Failed to copy static files")?;
+    match args.command {
+        Command::Build {
+            site,
+            output,
+            gemini,
+        } => build(&site, Some(&output), |a| {
+            // Build the capsule first, so that a site that can't have one
+            // fails before anything is written.
+            let capsule = match &gemini {
+                Some(gemini) => Some((gemini, a.gemini_output()?)),
+                None => None,
+            };
+            blog_engine::write(a, &output)?;
+            match capsule {
+                Some((gemini, tree)) => tree.write(gemini),
+                None => Ok(()),
+            }
+        })?,
+        Command::Check { site, format } => {
+            // Always collect all the errors when checking.
+            let options = Options {
+                keep_going: true,
+                ..site.options()
+            };
+            let (errors, lints) = match blog_engine::load(&site.source, &options) {
+                Ok(loaded) => (loaded.errors, loaded.lints),
+                Err(e) => {
+                    // Errors that can't be skipped are reported as a
+                    // diagnostic too.
+                    let mut errors = util::ErrorLog::new(true);
+                    errors.push(e)?;
+                    (errors, Default::default())
+                }
+            };
+            report(&errors, &lints, format)?;
+            if lints.error_count() > 0 || !errors.is_empty() {
+                bail!(
+                    "Check failed with {} error(s)",
+                    lints.error_count() + errors.len()
+                );
+            }
+        }
+        Command::Serve {
+            site,
+            output,
+            address,
+        } => {
+            // Keep serving after a failed build so the error can be fixed.
+            let reload = Arc::new(LiveReload::new());
+            if let Err(e) = build(&site, Some(&output), |a| blog_engine::write(a, &output)) {
+                eprintln!("Error: {e}");
+                reload.fail(&e.diagnostics);
+            }
+
+            serve::serve(&output, &address, Some(reload.clone()))?;
+            eprintln!("Serving site at http://{address}/");
+
+            serve::watch(
+                &[site.source.clone(), blog_engine::static_dir(&site.source)],
+                || match build(&site, Some(&output), |a| blog_engine::write(a, &output)) {
+                    Ok(()) => {
+                        eprintln!("Rebuilt site");
+                        reload.reload();
+                    }
+                    Err(e) => {
+                        eprintln!("Error: {e}");
+                        reload.fail(&e.diagnostics);
+                    }
+                },
+            )?;
+        }
+        Command::Export {
+            format: Export::Epub { site, tag, output },
+        } => build(&site, None, |a| {
+            blog_engine::write_epub(a, tag.as_deref(), &output)
+        })?,
+        Command::New { source, title } => {
+            let path = source
+                .join("posts")
+                .join(format!("{}.md", util::slugify(&title)));
+            if path.exists() {
+                bail!("{} already exists", path.display());
+            }
+            fs::write(
+                &path,
+                format!(":title {title}\n:date {}\n\n", util::today()),
+            )
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("{}", path.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// A failed build along with the diagnostics that explain it.
+#[derive(Debug)]
+struct BuildError {
+    error: anyhow::Error,
+    diagnostics: Vec<lint::Diagnostic>,
+}
+
+impl From<anyhow::Error> for BuildError {
+    fn from(error: anyhow::Error) -> Self {
+        BuildError {
+            diagnostics: vec![lint::Diagnostic::from(&error)],
+            error,
+        }
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.error)
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Load the site and hand it to `write` if it has no errors.
+///
+/// Filter command results are reused from the output directory if given.
+fn build(
+    args: &SiteArgs,
+    output: Option<&Path>,
+    write: impl FnOnce(&Site) -> Result<()>,
+) -> Result<(), BuildError> {
+    let options = Options {
+        cache_dir: output.map(Path::to_owned),
+        ..args.options()
+    };
+    let loaded = blog_engine::load(&args.source, &options)?;
+    report(&loaded.errors, &loaded.lints, DiagnosticFormat::Human)?;
+    let failed = |error| BuildError {
+        error,
+        diagnostics: diagnostics(&loaded.errors, &loaded.lints).collect(),
+    };
+
+    if loaded.lints.error_count() > 0 {
+        return Err(failed(anyhow!(
+            "Build failed with {} lint error(s)",
+            loaded.lints.error_count()
+        )));
+    }
+
+    write(&loaded.site)?;
+
+    if !loaded.errors.is_empty() {
+        return Err(failed(anyhow!(
+            "Skipped {} broken item(s)",
+            loaded.errors.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Diagnostics for errors and lints.
+fn diagnostics<'a>(
+    errors: &'a util::ErrorLog,
+    lints: &'a lint::Lints,
+) -> impl Iterator<Item = lint::Diagnostic> + 'a {
+    errors
+        .errors()
+        .iter()
+        .map(lint::Diagnostic::from)
+        .chain(lints.diagnostics.iter().cloned())
+}
 
+/// Print errors and lint diagnostics.
+fn report(errors: &util::ErrorLog, lints: &lint::Lints, format: DiagnosticFormat) -> Result<()> {
+    for diagnostic in diagnostics(errors, lints) {
+        match format {
+            DiagnosticFormat::Human => eprintln!("{diagnostic}"),
+            DiagnosticFormat::Json => println!("{}", serde_json::to_string(&diagnostic)?),
+        }
+    }
     Ok(())
 }
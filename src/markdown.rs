@@ -0,0 +1,521 @@
+//! Markdown rendering with build-time syntax highlighting.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Write},
+    sync::OnceLock,
+};
+
+use anyhow::{bail, Context, Result};
+use latex2mathml::DisplayStyle;
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use syntect::{
+    highlighting::ThemeSet,
+    html::{ClassStyle, ClassedHTMLGenerator},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+use crate::{input::MarkdownOptions, util};
+
+/// Highlighting theme used if the settings don't name one.
+pub const DEFAULT_THEME: &str = "InspiredGitHub";
+
+/// Prefix of the highlighting classes, so they don't clash with the site
+/// stylesheet.
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Convert markdown to HTML.
+///
+/// Fenced code blocks with a language tag the highlighter knows are turned
+/// into spans with highlighting classes, other code blocks are left plain.
+/// Headings get `id` anchors from their text and a link to themselves. If
+/// `toc` is set, a table of contents of the headings is put at the start.
+/// TeX math between `$` or `$$` is turned into MathML. Footnotes are turned
+/// into sidenotes next to their references if the options say so.
+pub fn to_html(text: &str, options: &MarkdownOptions, toc: bool) -> Result<String> {
+    let mut events = Vec::new();
+    // Language and text of the code block being read.
+    let mut code: Option<(String, String)> = None;
+
+    for event in Parser::new_ext(text, parser_options(options)) {
+        match (&mut code, event) {
+            (None, Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info)))) => {
+                let lang = info
+                    .split(|c: char| c.is_whitespace() || c == ',')
+                    .next()
+                    .unwrap_or_default();
+                code = Some((lang.to_owned(), String::new()));
+            }
+            (Some((_, buf)), Event::Text(text)) => buf.push_str(&text),
+            (Some((lang, buf)), Event::End(TagEnd::CodeBlock)) => {
+                events.push(Event::Html(code_block(lang, buf).into()));
+                code = None;
+            }
+            (None, Event::Html(html) | Event::InlineHtml(html))
+                if !options.raw_html.unwrap_or(true) =>
+            {
+                events.push(Event::Text(html))
+            }
+            (None, Event::InlineMath(tex)) => {
+                events.push(Event::InlineHtml(math(&tex, DisplayStyle::Inline)?.into()))
+            }
+            (None, Event::DisplayMath(tex)) => {
+                events.push(Event::InlineHtml(math(&tex, DisplayStyle::Block)?.into()))
+            }
+            (_, event) => events.push(event),
+        }
+    }
+
+    if options.sidenotes.unwrap_or(false) {
+        events = sidenotes(events);
+    }
+    let (events, headings) = anchor_headings(events);
+    let mut html = String::new();
+    if toc {
+        html.push_str(&table_of_contents(&headings));
+    }
+    pulldown_cmark::html::push_html(&mut html, events.into_iter());
+    Ok(html)
+}
+
+/// Error context for math that can't be converted, with the TeX source.
+#[derive(Debug)]
+pub struct MalformedMath(pub String);
+
+impl fmt::Display for MalformedMath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Malformed math `{}`", self.0)
+    }
+}
+
+pub(crate) fn math(tex: &str, style: DisplayStyle) -> Result<String> {
+    let context = || MalformedMath(tex.trim().to_owned());
+    let mathml = latex2mathml::latex_to_mathml(tex, style).with_context(context)?;
+
+    // Some errors are only reported as text in the output.
+    const PARSE_ERROR: &str = "[PARSE ERROR: ";
+    if let Some(pos) = mathml.find(PARSE_ERROR) {
+        let error = &mathml[pos + PARSE_ERROR.len()..];
+        let error = error.split_once("]</mtext>").map_or(error, |(a, _)| a);
+        return Err(anyhow::anyhow!("{error}").context(context()));
+    }
+    Ok(mathml)
+}
+
+/// Convert a single line of markdown to HTML without a paragraph around it.
+///
+/// Lines that markdown would turn into something other than a paragraph,
+/// like a heading or a list, are escaped as plain text instead.
+pub fn inline_to_html(text: &str, options: &MarkdownOptions) -> Result<String> {
+    let html = to_html(text, options, false)?;
+    match html
+        .strip_prefix("<p>")
+        .and_then(|a| a.strip_suffix("</p>\n"))
+    {
+        Some(inline) if !inline.contains("<p>") => Ok(inline.to_owned()),
+        _ => Ok(util::escape_html(text)),
+    }
+}
+
+pub(crate) fn parser_options(options: &MarkdownOptions) -> Options {
+    let mut ret = Options::ENABLE_HEADING_ATTRIBUTES;
+    for (is_enabled, option) in [
+        (options.tables.unwrap_or(true), Options::ENABLE_TABLES),
+        (options.footnotes.unwrap_or(true), Options::ENABLE_FOOTNOTES),
+        (
+            options.strikethrough.unwrap_or(true),
+            Options::ENABLE_STRIKETHROUGH,
+        ),
+        (
+            options.task_lists.unwrap_or(true),
+            Options::ENABLE_TASKLISTS,
+        ),
+        (options.math.unwrap_or(true), Options::ENABLE_MATH),
+        (
+            options.smart_punctuation.unwrap_or(false),
+            Options::ENABLE_SMART_PUNCTUATION,
+        ),
+    ] {
+        if is_enabled {
+            ret.insert(option);
+        }
+    }
+    ret
+}
+
+/// Turn footnotes into numbered notes placed after their first reference.
+///
+/// The notes are shown in the page margin or toggled open by clicking the
+/// number, depending on the stylesheet. Footnotes that aren't referenced
+/// are left at the end of the document, numbered after the notes.
+fn sidenotes(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut ret = Vec::with_capacity(events.len());
+    let mut notes: BTreeMap<String, Vec<Event>> = BTreeMap::new();
+    // Label and contents of the footnote being read.
+    let mut note: Option<(String, Vec<Event>)> = None;
+    for event in events {
+        match (&mut note, event) {
+            (None, Event::Start(Tag::FootnoteDefinition(label))) => {
+                note = Some((label.to_string(), Vec::new()));
+            }
+            (Some((label, contents)), Event::End(TagEnd::FootnoteDefinition)) => {
+                notes.insert(std::mem::take(label), std::mem::take(contents));
+                note = None;
+            }
+            (Some((_, contents)), event) => contents.push(event),
+            (None, event) => ret.push(event),
+        }
+    }
+
+    // HTML of the later references to a note by label, notes are numbered
+    // in the order of their first references.
+    let mut refs: BTreeMap<String, String> = BTreeMap::new();
+    // Notes with block content that stay footnotes.
+    let mut footnotes = String::new();
+    for event in &mut ret {
+        let Event::FootnoteReference(label) = event else {
+            continue;
+        };
+        let label = label.to_string();
+        if let Some(html) = refs.get(&label) {
+            *event = Event::InlineHtml(html.clone().into());
+            continue;
+        }
+        let Some(contents) = notes.remove(&label) else {
+            continue;
+        };
+        let n = refs.len() + 1;
+
+        if contents.iter().any(is_block) {
+            // Lists, code and the like can't go inside the referring
+            // paragraph, so the note is a footnote that keeps its number.
+            let id = util::escape_html(&label);
+            let html = format!("<sup class=\"footnote-reference\"><a href=\"#{id}\">{n}</a></sup>");
+            push_footnote(&mut footnotes, &label, n, contents);
+            *event = Event::InlineHtml(html.clone().into());
+            refs.insert(label, html);
+            continue;
+        }
+        refs.insert(
+            label,
+            format!("<a class=\"sidenote-number\" href=\"#sidenote-ref-{n}\">{n}</a>"),
+        );
+
+        // Notes go inside the referring paragraph, so their paragraphs are
+        // turned into line breaks.
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, contents.into_iter());
+        let html = html.trim();
+        let html = html.strip_prefix("<p>").unwrap_or(html);
+        let html = html.strip_suffix("</p>").unwrap_or(html);
+        let html = html.replace("</p>\n<p>", "<br>");
+        *event = Event::InlineHtml(
+            format!(
+                "<label for=\"sidenote-{n}\" class=\"sidenote-number\" id=\"sidenote-ref-{n}\">{n}</label>\
+                 <input type=\"checkbox\" id=\"sidenote-{n}\" class=\"sidenote-toggle\">\
+                 <span class=\"sidenote\"><a class=\"sidenote-number\" href=\"#sidenote-ref-{n}\">{n}</a> {html}</span>"
+            )
+            .into(),
+        );
+    }
+
+    // Notes that aren't referenced continue the numbering.
+    for (i, (label, contents)) in notes.into_iter().enumerate() {
+        push_footnote(&mut footnotes, &label, refs.len() + i + 1, contents);
+    }
+    if !footnotes.is_empty() {
+        ret.push(Event::Html(footnotes.into()));
+    }
+    ret
+}
+
+/// Write a footnote definition numbered `n` at the end of the document.
+fn push_footnote(out: &mut String, label: &str, n: usize, contents: Vec<Event<'_>>) {
+    let _ = writeln!(
+        out,
+        "<div class=\"footnote-definition\" id=\"{}\"><sup class=\"footnote-definition-label\">{n}</sup>",
+        util::escape_html(label)
+    );
+    pulldown_cmark::html::push_html(out, contents.into_iter());
+    out.push_str("</div>\n");
+}
+
+/// Whether a footnote event is block content that can't be shown inline.
+fn is_block(event: &Event) -> bool {
+    match event {
+        Event::Start(tag) => !matches!(
+            tag,
+            Tag::Paragraph
+                | Tag::Emphasis
+                | Tag::Strong
+                | Tag::Strikethrough
+                | Tag::Link { .. }
+                | Tag::Image { .. }
+        ),
+        Event::Html(_) | Event::Rule => true,
+        _ => false,
+    }
+}
+
+/// Anchor for a heading that isn't in `ids` yet, from an explicit id or
+/// from the heading's text.
+///
+/// Repeated anchors get a number suffix. The anchor is added to `ids`.
+pub(crate) fn heading_id(ids: &mut BTreeSet<String>, explicit: Option<&str>, text: &str) -> String {
+    let mut anchor = match explicit {
+        Some(id) => id.to_owned(),
+        None if util::slugify(text).is_empty() => "section".to_owned(),
+        None => util::slugify(text),
+    };
+    if ids.contains(&anchor) {
+        anchor = (2..)
+            .map(|i| format!("{anchor}-{i}"))
+            .find(|a| !ids.contains(a))
+            .unwrap();
+    }
+    ids.insert(anchor.clone());
+    anchor
+}
+
+/// Heading of a document, for the table of contents.
+struct Heading {
+    level: usize,
+    id: String,
+    text: String,
+}
+
+/// Give headings unique ids and self-links.
+///
+/// Headings with an explicit `{#id}` keep it, others get one from their
+/// text.
+fn anchor_headings(events: Vec<Event<'_>>) -> (Vec<Event<'_>>, Vec<Heading>) {
+    let mut ret = Vec::with_capacity(events.len());
+    let mut headings = Vec::new();
+    let mut ids = BTreeSet::new();
+    // Position of the start of the heading being read and its text.
+    let mut current: Option<(usize, String)> = None;
+
+    for event in events {
+        match (&mut current, event) {
+            (_, event @ Event::Start(Tag::Heading { .. })) => {
+                current = Some((ret.len(), String::new()));
+                ret.push(event);
+            }
+            (Some((_, text)), event @ (Event::Text(_) | Event::Code(_))) => {
+                if let Event::Text(a) | Event::Code(a) = &event {
+                    text.push_str(a);
+                }
+                ret.push(event);
+            }
+            (Some((start, text)), Event::End(TagEnd::Heading(level))) => {
+                let Event::Start(Tag::Heading { id, .. }) = &mut ret[*start] else {
+                    unreachable!()
+                };
+                let anchor = heading_id(&mut ids, id.as_deref(), text);
+                *id = Some(anchor.clone().into());
+
+                ret.push(Event::Html(
+                    format!(
+                        " <a class=\"anchor\" href=\"#{}\" aria-hidden=\"true\">#</a>",
+                        util::escape_html(&anchor)
+                    )
+                    .into(),
+                ));
+                ret.push(Event::End(TagEnd::Heading(level)));
+                headings.push(Heading {
+                    level: level as usize,
+                    id: anchor,
+                    text: std::mem::take(text),
+                });
+                current = None;
+            }
+            (_, event) => ret.push(event),
+        }
+    }
+    (ret, headings)
+}
+
+/// Nested list of links to headings.
+fn table_of_contents(headings: &[Heading]) -> String {
+    if headings.is_empty() {
+        return String::new();
+    }
+
+    let mut html = String::from("<nav class=\"toc\">\n");
+    // Heading levels of the open lists.
+    let mut open: Vec<usize> = Vec::new();
+    for heading in headings {
+        while open.last().is_some_and(|&a| a > heading.level) {
+            html.push_str("</li>\n</ul>\n");
+            open.pop();
+        }
+        if open.last() == Some(&heading.level) {
+            html.push_str("</li>\n");
+        } else {
+            html.push_str("<ul>\n");
+            open.push(heading.level);
+        }
+        let _ = write!(
+            html,
+            "<li><a href=\"#{}\">{}</a>",
+            util::escape_html(&heading.id),
+            util::escape_html(&heading.text)
+        );
+    }
+    for _ in open {
+        html.push_str("</li>\n</ul>\n");
+    }
+    html.push_str("</nav>\n");
+    html
+}
+
+/// Render a fenced code block, highlighting it if the language is known.
+pub(crate) fn code_block(lang: &str, code: &str) -> String {
+    let syntaxes = syntaxes();
+    let highlighted = syntaxes.find_syntax_by_token(lang).and_then(|syntax| {
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(syntax, syntaxes, CLASS_STYLE);
+        for line in LinesWithEndings::from(code) {
+            generator
+                .parse_html_for_line_which_includes_newline(line)
+                .ok()?;
+        }
+        Some(generator.finalize())
+    });
+
+    match highlighted {
+        Some(html) => format!(
+            "<pre class=\"hl-code\"><code class=\"language-{}\">{html}</code></pre>\n",
+            util::escape_html(lang)
+        ),
+        None if lang.is_empty() => format!("<pre><code>{}</code></pre>\n", util::escape_html(code)),
+        None => format!(
+            "<pre><code class=\"language-{}\">{}</code></pre>\n",
+            util::escape_html(lang),
+            util::escape_html(code)
+        ),
+    }
+}
+
+/// Stylesheet for the highlighting classes in a named theme.
+pub fn stylesheet(theme: &str) -> Result<String> {
+    let themes = ThemeSet::load_defaults();
+    let Some(theme_data) = themes.themes.get(theme) else {
+        let known: Vec<&str> = themes.themes.keys().map(|a| a.as_str()).collect();
+        bail!(
+            "Unknown highlight theme {theme:?}, available themes are {}",
+            known.join(", ")
+        );
+    };
+    Ok(syntect::html::css_for_theme_with_class_style(
+        theme_data,
+        CLASS_STYLE,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlighting() {
+        let html = to_html("```rust\nfn main() {}\n```\n", &Default::default(), false).unwrap();
+        assert!(html.starts_with("<pre class=\"hl-code\"><code class=\"language-rust\">"));
+        assert!(html.contains("<span class=\"hl-"));
+
+        // Unknown languages are left plain, but still escaped.
+        assert_eq!(
+            to_html("```nosuchlang\na < b\n```\n", &Default::default(), false).unwrap(),
+            "<pre><code class=\"language-nosuchlang\">a &lt; b\n</code></pre>\n"
+        );
+        assert_eq!(
+            to_html("    indented\n", &Default::default(), false).unwrap(),
+            "<pre><code>indented\n</code></pre>\n"
+        );
+
+        assert!(stylesheet(DEFAULT_THEME).unwrap().contains(".hl-"));
+        assert!(stylesheet("no such theme").is_err());
+    }
+
+    #[test]
+    fn headings() {
+        let text = "# Intro\n\n## Setup\n\n## Setup\n\n# Usage {#use}\n";
+        let html = to_html(text, &Default::default(), true).unwrap();
+        assert_eq!(
+            html,
+            "<nav class=\"toc\">\n<ul>\n\
+             <li><a href=\"#intro\">Intro</a><ul>\n\
+             <li><a href=\"#setup\">Setup</a></li>\n\
+             <li><a href=\"#setup-2\">Setup</a></li>\n</ul>\n</li>\n\
+             <li><a href=\"#use\">Usage</a></li>\n</ul>\n</nav>\n\
+             <h1 id=\"intro\">Intro <a class=\"anchor\" href=\"#intro\" aria-hidden=\"true\">#</a></h1>\n\
+             <h2 id=\"setup\">Setup <a class=\"anchor\" href=\"#setup\" aria-hidden=\"true\">#</a></h2>\n\
+             <h2 id=\"setup-2\">Setup <a class=\"anchor\" href=\"#setup-2\" aria-hidden=\"true\">#</a></h2>\n\
+             <h1 id=\"use\">Usage <a class=\"anchor\" href=\"#use\" aria-hidden=\"true\">#</a></h1>\n"
+        );
+
+        let table = "| a |\n|---|\n| b |\n";
+        assert!(to_html(table, &Default::default(), false)
+            .unwrap()
+            .contains("<table>"));
+        let options = MarkdownOptions {
+            tables: Some(false),
+            ..Default::default()
+        };
+        assert!(!to_html(table, &options, false).unwrap().contains("<table>"));
+    }
+
+    #[test]
+    fn math() {
+        let html = to_html("Euler's $e^{i\\pi} = -1$.\n", &Default::default(), false).unwrap();
+        assert!(html.starts_with(
+            "<p>Euler's <math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"inline\">"
+        ));
+        let html = to_html("$$\n\\sum_i x_i\n$$\n", &Default::default(), false).unwrap();
+        assert!(html.contains("display=\"block\""));
+
+        for tex in ["$\\left( x$", "$\\nosuchcommand$"] {
+            assert!(to_html(tex, &Default::default(), false).is_err(), "{tex}");
+        }
+    }
+
+    #[test]
+    fn sidenotes() {
+        let text = "Text[^a] and[^b] again[^a].\n\n[^a]: First.\n\n    More.\n\n[^b]: Second.\n\n[^c]: Unused.\n";
+        let options = MarkdownOptions {
+            sidenotes: Some(true),
+            ..Default::default()
+        };
+        let html = to_html(text, &options, false).unwrap();
+        assert!(html.starts_with(
+            "<p>Text<label for=\"sidenote-1\" class=\"sidenote-number\" id=\"sidenote-ref-1\">1</label>\
+             <input type=\"checkbox\" id=\"sidenote-1\" class=\"sidenote-toggle\">\
+             <span class=\"sidenote\"><a class=\"sidenote-number\" href=\"#sidenote-ref-1\">1</a> First.<br>More.</span> and"
+        ));
+        assert!(html.contains("id=\"sidenote-ref-2\">2</label>"));
+        assert!(
+            html.contains(" again<a class=\"sidenote-number\" href=\"#sidenote-ref-1\">1</a>.</p>")
+        );
+        assert!(html.ends_with(
+            "<div class=\"footnote-definition\" id=\"c\"><sup class=\"footnote-definition-label\">3</sup>\n\
+             <p>Unused.</p>\n</div>\n"
+        ), "{html}");
+
+        // Notes with block content stay footnotes.
+        let text = "One[^a] two[^b].\n\n[^a]: Inline.\n\n[^b]: A list:\n\n    - item\n";
+        let html = to_html(text, &options, false).unwrap();
+        assert!(
+            html.contains(" two<sup class=\"footnote-reference\"><a href=\"#b\">2</a></sup>.</p>")
+        );
+        assert!(html.ends_with(
+            "<div class=\"footnote-definition\" id=\"b\"><sup class=\"footnote-definition-label\">2</sup>\n\
+             <p>A list:</p>\n<ul>\n<li>item</li>\n</ul>\n</div>\n"
+        ), "{html}");
+    }
+}
@@ -0,0 +1,241 @@
+//! Posts written in Emacs Org-mode markup.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+};
+
+use anyhow::Result;
+use lazy_regex::regex;
+use orgize::{
+    export::{DefaultHtmlHandler, HtmlHandler},
+    Element, Org,
+};
+
+use crate::{input::PostHeader, markdown, util};
+
+/// Convert an Org document to HTML.
+///
+/// Source blocks are highlighted like markdown code blocks and headings get
+/// `id` anchors from their text. Footnote definitions go at the end of the
+/// document.
+pub fn to_html(text: &str) -> Result<String> {
+    let (text, footnotes) = split_footnotes(text);
+    let mut handler = Handler::default();
+    let mut html = Vec::new();
+    Org::parse(&text).write_html_custom(&mut html, &mut handler)?;
+    let inline = std::mem::take(&mut handler.inline);
+    for (label, contents) in footnotes.into_iter().chain(inline) {
+        let label = util::escape_html(&label);
+        write!(
+            html,
+            "<div class=\"footnote-definition\" id=\"fn-{label}\">\
+             <sup class=\"footnote-definition-label\"><a href=\"#fnref-{label}\">{label}</a></sup>"
+        )?;
+        Org::parse(&contents).write_html_custom(&mut html, &mut handler)?;
+        write!(html, "</div>")?;
+    }
+    Ok(String::from_utf8(html)?)
+}
+
+/// Take the footnote definitions out of an Org document.
+///
+/// orgize's parse tree checks in debug builds reject the footnote
+/// definitions it parses, so they never get to it. Returns the document
+/// without the definitions and the labels and contents of the definitions.
+fn split_footnotes(text: &str) -> (String, Vec<(String, String)>) {
+    let mut body = String::new();
+    let mut footnotes: Vec<(String, String)> = Vec::new();
+    let mut in_footnote = false;
+    let mut in_block = false;
+    let mut blank_lines = 0;
+    for line in text.lines() {
+        // Lines in blocks are contents, whatever they look like.
+        if in_block {
+            in_block = !regex!(r"(?i)^\s*#\+END_").is_match(line);
+        } else if regex!(r"(?i)^\s*#\+BEGIN_").is_match(line) {
+            in_block = true;
+            blank_lines = 0;
+        } else {
+            // Inline footnotes like `[fn::text]` and `[fn:name:text]` have
+            // a colon after the label.
+            if let Some(caps) = regex!(r"^\[fn:([^\]\s:]+)\]\s?(.*)$").captures(line) {
+                footnotes.push((caps[1].to_owned(), format!("{}\n", &caps[2])));
+                in_footnote = true;
+                blank_lines = 0;
+                continue;
+            }
+            // A definition ends at a headline or at two blank lines.
+            blank_lines = if line.trim().is_empty() {
+                blank_lines + 1
+            } else {
+                0
+            };
+            if regex!(r"^\*+\s").is_match(line) || blank_lines >= 2 {
+                in_footnote = false;
+            }
+        }
+        match footnotes.last_mut() {
+            Some((_, contents)) if in_footnote => {
+                contents.push_str(line);
+                contents.push('\n');
+            }
+            _ => {
+                body.push_str(line);
+                body.push('\n');
+            }
+        }
+    }
+    (body, footnotes)
+}
+
+/// Fill in the header fields that the document sets with `#+TITLE` and
+/// `#+DATE` keywords and that the header leaves empty.
+pub fn fill_header(header: &mut PostHeader, text: &str) {
+    let (text, _) = split_footnotes(text);
+    let org = Org::parse(&text);
+    for keyword in org.keywords() {
+        let value = keyword.value.trim();
+        match keyword.key.to_uppercase().as_str() {
+            "TITLE" if header.title.is_empty() => header.title = value.to_owned(),
+            "DATE" if header.date.is_empty() => {
+                // Org timestamps look like `<2024-01-05 Fri>`.
+                header.date = match regex!(r"\d{4}-\d{2}-\d{2}").find(value) {
+                    Some(date) => date.as_str().to_owned(),
+                    None => value.to_owned(),
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+struct Handler {
+    inner: DefaultHtmlHandler,
+    /// Heading anchors used so far.
+    ids: BTreeSet<String>,
+    /// Labels and contents of inline footnote definitions.
+    inline: Vec<(String, String)>,
+}
+
+impl HtmlHandler<io::Error> for Handler {
+    fn start<W: Write>(&mut self, mut w: W, element: &Element) -> io::Result<()> {
+        match element {
+            // The page template has the main element.
+            Element::Document { .. } => {}
+            Element::Title(title) => {
+                let id = markdown::heading_id(&mut self.ids, None, &title.raw);
+                write!(
+                    w,
+                    "<h{} id=\"{}\">",
+                    title.level.min(6),
+                    util::escape_html(&id)
+                )?;
+            }
+            Element::SourceBlock(block) => write!(
+                w,
+                "{}",
+                markdown::code_block(&block.language, &block.contents)
+            )?,
+            Element::FnRef(fn_ref) => {
+                let mut label = fn_ref.label.to_string();
+                // Inline definitions go with the others, and anonymous
+                // ones are numbered.
+                if let Some(definition) = &fn_ref.definition {
+                    if label.is_empty() {
+                        label = format!("inline-{}", self.inline.len() + 1);
+                    }
+                    self.inline.push((label.clone(), format!("{definition}\n")));
+                }
+                let label = util::escape_html(&label);
+                write!(
+                    w,
+                    "<sup class=\"footnote-reference\"><a href=\"#fn-{label}\" id=\"fnref-{label}\">{label}</a></sup>"
+                )?;
+            }
+            _ => self.inner.start(w, element)?,
+        }
+        Ok(())
+    }
+
+    fn end<W: Write>(&mut self, w: W, element: &Element) -> io::Result<()> {
+        match element {
+            Element::Document { .. } => {}
+            _ => self.inner.end(w, element)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn org() {
+        let text = "\
+#+TITLE: Org post
+#+DATE: <2024-01-05 Fri>
+
+* Heading
+- item with [[https://example.com][a link]][fn:1]
+
+#+BEGIN_SRC rust
+fn main() {}
+#+END_SRC
+
+| a | b |
+
+[fn:1] The note.
+";
+        let mut header = PostHeader::default();
+        fill_header(&mut header, text);
+        assert_eq!(header.title, "Org post");
+        assert_eq!(header.date, "2024-01-05");
+
+        let html = to_html(text).unwrap();
+        assert!(!html.contains("<main>"));
+        assert!(html.contains("<h1 id=\"heading\">Heading</h1>"));
+        assert!(html.contains("<a href=\"https://example.com\">a link</a>"));
+        assert!(html.contains("<pre class=\"hl-code\"><code class=\"language-rust\">"));
+        assert!(html.contains("<table>"));
+        assert!(html.contains("<div class=\"footnote-definition\" id=\"fn-1\">"));
+    }
+
+    #[test]
+    fn footnotes() {
+        // Test builds have debug assertions on, so this also checks that
+        // orgize's parse tree checks don't trip on the definitions.
+        let html = to_html(
+            "Text[fn:a] more[fn:b].\n\n[fn:a] First\nline.\n\n[fn:b] Second.\n* Next\nAfter.\n",
+        )
+        .unwrap();
+        assert!(html.contains("<h1 id=\"next\">Next</h1><section><p>After.</p></section>"));
+        assert!(html.ends_with(
+            "<div class=\"footnote-definition\" id=\"fn-a\">\
+             <sup class=\"footnote-definition-label\"><a href=\"#fnref-a\">a</a></sup>\
+             <section><p>First\nline.</p></section></div>\
+             <div class=\"footnote-definition\" id=\"fn-b\">\
+             <sup class=\"footnote-definition-label\"><a href=\"#fnref-b\">b</a></sup>\
+             <section><p>Second.</p></section></div>"
+        ));
+    }
+
+    #[test]
+    fn footnote_lookalikes() {
+        let html = to_html(
+            "#+BEGIN_SRC markdown\n[fn:x] not a footnote\n#+END_SRC\nAfter block.\n\n\
+             [fn::inline] text\n",
+        )
+        .unwrap();
+        assert!(html.contains("[fn:x] not a footnote"), "{html}");
+        assert!(html.contains("</pre>\n<p>After block.</p>"), "{html}");
+        assert!(html.contains("<a href=\"#fn-inline-1\" id=\"fnref-inline-1\">"));
+        assert!(html.ends_with(
+            "<div class=\"footnote-definition\" id=\"fn-inline-1\">\
+             <sup class=\"footnote-definition-label\"><a href=\"#fnref-inline-1\">inline-1</a></sup>\
+             <section><p>inline</p></section></div>"
+        ));
+    }
+}
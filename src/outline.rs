@@ -0,0 +1,112 @@
+//! Posts written as an indented outline of headlines.
+
+use std::fmt::Write;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::{input::MarkdownOptions, markdown, util};
+
+/// Items of an outline and their children.
+type Items = Vec<((String,), ((ItemAttributes,), Outline))>;
+
+/// Outline post body.
+///
+/// Each item is a headline followed by its attributes and its child items,
+/// indented below it:
+///
+/// ```notrust
+/// Headline with *inline markdown*
+///   :id anchor
+///   :collapsed true
+///   Child item
+/// ```
+#[derive(Default, Debug, Deserialize)]
+pub struct Outline(pub Items);
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ItemAttributes {
+    /// Anchor for linking to the item.
+    pub id: String,
+    /// Whether the item's children start out hidden.
+    pub collapsed: bool,
+}
+
+/// Convert an outline into nested HTML lists.
+///
+/// Headlines are rendered as inline markdown, HTML in them is escaped.
+pub fn to_html(text: &str, options: &MarkdownOptions) -> Result<String> {
+    let outline: Outline = idm::from_str(text).context("Failed to parse outline body")?;
+    let options = MarkdownOptions {
+        raw_html: Some(false),
+        ..*options
+    };
+    let mut html = String::new();
+    push(&mut html, &outline, &options)?;
+    Ok(html)
+}
+
+fn push(buf: &mut String, outline: &Outline, options: &MarkdownOptions) -> Result<()> {
+    if outline.0.is_empty() {
+        return Ok(());
+    }
+    let _ = write!(buf, "<ul class='outline'>");
+    for ((head,), ((attrs,), body)) in &outline.0 {
+        if attrs.id.is_empty() {
+            let _ = write!(buf, "<li>");
+        } else {
+            let _ = write!(buf, "<li id=\"{}\">", util::escape_html(&attrs.id));
+        }
+
+        let head = if head.is_empty() {
+            "<br/>".to_owned()
+        } else {
+            markdown::inline_to_html(head, options)
+                .with_context(|| format!("Failed to render outline item `{head}`"))?
+        };
+        if attrs.collapsed && !body.0.is_empty() {
+            let _ = write!(buf, "<details><summary>{head}</summary>");
+            push(buf, body, options)?;
+            let _ = write!(buf, "</details>");
+        } else {
+            buf.push_str(&head);
+            push(buf, body, options)?;
+        }
+        let _ = write!(buf, "</li>");
+    }
+    let _ = write!(buf, "</ul>");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outline() {
+        let text = "\
+Plain <b> & *emphasis*
+  :id first
+  [link](https://example.com) and `code`
+# Not a heading
+Folded
+  :collapsed true
+  Hidden
+";
+        assert_eq!(
+            to_html(text, &Default::default()).unwrap(),
+            "<ul class='outline'>\
+             <li id=\"first\">Plain &lt;b&gt; &amp; <em>emphasis</em>\
+             <ul class='outline'><li><a href=\"https://example.com\">link</a> and <code>code</code></li></ul></li>\
+             <li># Not a heading</li>\
+             <li><details><summary>Folded</summary><ul class='outline'><li>Hidden</li></ul></details></li>\
+             </ul>"
+        );
+
+        // Unknown attributes are errors instead of vanishing from the post.
+        let err = to_html("Item\n  :colapsed true\n", &Default::default()).unwrap_err();
+        assert!(format!("{err:#}").contains("colapsed"), "{err:#}");
+        assert!(to_html("Item\n  :tada: shipped\n", &Default::default()).is_err());
+    }
+}
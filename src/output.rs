@@ -0,0 +1,419 @@
+//! Tree of files that make up the built site.
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fs,
+    hash::Hash,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use askama::Template;
+use serde::Serialize;
+
+use crate::{
+    cache::{self, BuildCache, Stamp},
+    theme::Templates,
+    util,
+};
+
+/// File that marks a directory as site output that can be replaced.
+pub const MARKER_FILE: &str = ".blog-engine-output";
+
+const MARKER_TEXT: &str =
+    "This directory is generated by blog-engine and is replaced on every build.\n";
+
+/// Sources of the compiled templates, for noticing when they change.
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("base.html", include_str!("../templates/base.html")),
+    ("feed.xml", include_str!("../templates/feed.xml")),
+    ("list.html", include_str!("../templates/list.html")),
+    ("post.html", include_str!("../templates/post.html")),
+];
+
+/// Page type that is rendered from templates into the output tree.
+pub trait Page: Template + Serialize + Hash {
+    /// Names of the templates the page is rendered with. The first one is
+    /// the page's own template and the rest are the ones it extends.
+    const TEMPLATES: &'static [&'static str];
+}
+
+/// Files of the output site by their path relative to the output directory.
+///
+/// Paths use forward slashes and can contain subdirectories.
+#[derive(Default)]
+pub struct OutputTree<'a> {
+    files: BTreeMap<String, OutputFile<'a>>,
+    /// Runtime templates that replace the compiled ones if set.
+    templates: Option<Arc<Templates>>,
+}
+
+/// A file in the output tree.
+pub struct OutputFile<'a> {
+    /// MIME type of the file.
+    pub content_type: String,
+    /// Hashes of what the file is built from.
+    pub stamp: Stamp,
+    content: Content<'a>,
+}
+
+enum Content<'a> {
+    /// Page that gets rendered when the file contents are needed.
+    Page(Box<dyn Fn() -> Result<String> + 'a>),
+    Data(Vec<u8>),
+}
+
+impl OutputFile<'_> {
+    /// Contents of the file, rendering it if it's a page.
+    pub fn data(&self) -> Result<Cow<'_, [u8]>> {
+        match &self.content {
+            Content::Page(render) => Ok(Cow::Owned(render()?.into_bytes())),
+            Content::Data(data) => Ok(Cow::Borrowed(data)),
+        }
+    }
+}
+
+impl<'a> OutputTree<'a> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Tree that renders pages with runtime templates.
+    pub fn with_templates(templates: Arc<Templates>) -> Self {
+        OutputTree {
+            templates: Some(templates),
+            ..Default::default()
+        }
+    }
+
+    /// Add a page, replacing any existing file at path.
+    pub fn insert_page<P: Page>(&mut self, path: impl Into<String>, page: &'a P) -> Result<()> {
+        let path = path.into();
+        check_path(&path)?;
+
+        let (sources, render): (Vec<&str>, Box<dyn Fn() -> Result<String>>) = match &self.templates
+        {
+            Some(templates) => {
+                let sources = P::TEMPLATES
+                    .iter()
+                    .map(|a| templates.source(a).unwrap_or_default())
+                    .collect();
+                let templates = templates.clone();
+                let render = move || templates.render(P::TEMPLATES[0], page);
+                (sources, Box::new(render))
+            }
+            None => {
+                let sources = P::TEMPLATES
+                    .iter()
+                    .map(|a| {
+                        BUILTIN_TEMPLATES
+                            .iter()
+                            .find(|(name, _)| name == a)
+                            .map_or("", |(_, source)| source)
+                    })
+                    .collect();
+                (sources, Box::new(move || Ok(page.render()?)))
+            }
+        };
+
+        self.files.insert(
+            path.clone(),
+            OutputFile {
+                content_type: mime_type(&path).to_owned(),
+                stamp: Stamp::new(page, &sources),
+                content: Content::Page(render),
+            },
+        );
+        Ok(())
+    }
+
+    /// Add a file with the given contents, replacing any existing file at
+    /// path.
+    pub fn insert_data(
+        &mut self,
+        path: impl Into<String>,
+        data: impl Into<Vec<u8>>,
+        content_type: impl Into<String>,
+    ) -> Result<()> {
+        let path = path.into();
+        check_path(&path)?;
+        let data = data.into();
+        self.files.insert(
+            path,
+            OutputFile {
+                content_type: content_type.into(),
+                stamp: Stamp::new(&data, &[]),
+                content: Content::Data(data),
+            },
+        );
+        Ok(())
+    }
+
+    /// Add the files under a directory, like static assets, at the root of
+    /// the tree. Does nothing if the directory doesn't exist.
+    pub fn insert_dir(&mut self, dir: impl AsRef<Path>) -> Result<()> {
+        let dir = dir.as_ref();
+        if !dir.exists() {
+            return Ok(());
+        }
+
+        for e in walkdir::WalkDir::new(dir) {
+            let e = e.with_context(|| format!("Failed to read {}", dir.display()))?;
+            if !e.file_type().is_file() {
+                continue;
+            }
+            let data = fs::read(e.path())
+                .with_context(|| format!("Failed to read {}", e.path().display()))?;
+            let path = e.path().strip_prefix(dir)?;
+            let Some(path) = path.to_str() else {
+                bail!("Non-UTF-8 file name {}", path.display());
+            };
+            let path = path.replace(std::path::MAIN_SEPARATOR, "/");
+            self.insert_data(&path, data, mime_type(&path))?;
+        }
+        Ok(())
+    }
+
+    /// Add the files of a directory embedded in the binary at the root of
+    /// the tree.
+    pub fn insert_embedded(&mut self, dir: &include_dir::Dir) -> Result<()> {
+        for file in dir.files() {
+            let Some(path) = file.path().to_str() else {
+                bail!("Non-UTF-8 file name {}", file.path().display());
+            };
+            let path = path.replace(std::path::MAIN_SEPARATOR, "/");
+            self.insert_data(&path, file.contents(), mime_type(&path))?;
+        }
+        for dir in dir.dirs() {
+            self.insert_embedded(dir)?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, path: &str) -> Option<&OutputFile<'a>> {
+        self.files.get(path)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &OutputFile<'a>)> {
+        self.files.iter().map(|(path, file)| (path.as_str(), file))
+    }
+
+    /// Write the tree into an output directory, replacing its previous
+    /// contents.
+    ///
+    /// The output directory must be empty, missing, or written by an earlier
+    /// call, so that an unrelated directory never gets clobbered. The tree is
+    /// first written into a temporary sibling directory that is then renamed
+    /// into place, so a failed write leaves the previous output intact. Files
+    /// that haven't changed since the previous write are carried over with
+    /// their modification times unchanged.
+    pub fn write(&self, output: impl AsRef<Path>) -> Result<()> {
+        let output = output.as_ref();
+        check_output_dir(output)?;
+
+        let staging = sibling(output, "new")?;
+        // Leftovers from an interrupted build.
+        remove_leftover(&staging)?;
+        fs::create_dir_all(&staging)
+            .with_context(|| format!("Failed to create {}", staging.display()))?;
+        // Mark the directory first, so that it can be cleaned up if the
+        // build is interrupted.
+        util::write_file(staging.join(MARKER_FILE), MARKER_TEXT)?;
+
+        let cache = match self.write_new(output, &staging) {
+            Ok(cache) => cache,
+            Err(e) => {
+                let _ = fs::remove_dir_all(&staging);
+                return Err(e);
+            }
+        };
+        // The cache lives outside the published tree. The old one goes
+        // before the swap, so it never describes the wrong output.
+        let cache_path = sibling(output, cache::CACHE_SUFFIX)?;
+        if cache_path.exists() {
+            fs::remove_file(&cache_path)
+                .with_context(|| format!("Failed to remove {}", cache_path.display()))?;
+        }
+
+        if output.exists() {
+            let old = sibling(output, "old")?;
+            remove_leftover(&old)?;
+            fs::rename(output, &old)
+                .with_context(|| format!("Failed to move {} aside", output.display()))?;
+            fs::rename(&staging, output)
+                .with_context(|| format!("Failed to move new site to {}", output.display()))?;
+            fs::remove_dir_all(&old)
+                .with_context(|| format!("Failed to remove {}", old.display()))?;
+        } else {
+            fs::rename(&staging, output)
+                .with_context(|| format!("Failed to move new site to {}", output.display()))?;
+        }
+        cache.save(&cache_path)
+    }
+
+    /// Write the tree into a new directory, reusing unchanged files from the
+    /// previous output.
+    fn write_new(&self, output: &Path, staging: &Path) -> Result<BuildCache> {
+        let old = match sibling(output, cache::CACHE_SUFFIX) {
+            Ok(path) => BuildCache::load(&path),
+            Err(_) => Default::default(),
+        };
+        let mut cache = BuildCache::default();
+
+        for (path, file) in self.iter() {
+            let target = staging.join(path);
+            if old.is_stale(output, path, file.stamp) {
+                let data = file
+                    .data()
+                    .with_context(|| format!("Failed to render {path}"))?;
+                util::write_file(&target, data)?;
+            } else {
+                util::link_file(output.join(path), &target)?;
+            }
+            cache.insert(path, file.stamp);
+        }
+
+        Ok(cache)
+    }
+}
+
+/// Make sure the output directory is safe to replace.
+fn check_output_dir(output: &Path) -> Result<()> {
+    if !output.exists() || output.join(MARKER_FILE).is_file() {
+        return Ok(());
+    }
+
+    let is_empty = fs::read_dir(output)
+        .with_context(|| format!("Failed to read {}", output.display()))?
+        .next()
+        .is_none();
+    if !is_empty {
+        bail!(
+            "Refusing to replace {}, it is not empty and was not created by a site build",
+            output.display()
+        );
+    }
+    Ok(())
+}
+
+/// Remove a temporary directory left behind by an earlier build.
+///
+/// Directories that weren't created by a build are never removed, since
+/// the name could be taken by something else.
+fn remove_leftover(dir: &Path) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    if dir.join(MARKER_FILE).is_file() {
+        return fs::remove_dir_all(dir)
+            .with_context(|| format!("Failed to remove {}", dir.display()));
+    }
+    if fs::remove_dir(dir).is_err() {
+        bail!(
+            "Refusing to remove {}, it is not empty and was not created by a site build",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// Hidden file or directory next to the output directory.
+pub(crate) fn sibling(output: &Path, suffix: &str) -> Result<PathBuf> {
+    let Some(name) = output.file_name() else {
+        bail!("Invalid output directory {}", output.display());
+    };
+    let mut sibling = std::ffi::OsString::from(".");
+    sibling.push(name);
+    sibling.push(format!(".{suffix}"));
+    Ok(output.with_file_name(sibling))
+}
+
+/// Make sure a path stays inside the output directory.
+fn check_path(path: &str) -> Result<()> {
+    let is_valid = !path.contains('\\')
+        && path
+            .split('/')
+            .all(|a| !a.is_empty() && a != "." && a != "..");
+    if !is_valid {
+        bail!("Invalid output path {path:?}");
+    }
+    Ok(())
+}
+
+/// Guess the MIME type of a file from its extension.
+pub fn mime_type(path: impl AsRef<Path>) -> &'static str {
+    match path
+        .as_ref()
+        .extension()
+        .and_then(|a| a.to_str())
+        .unwrap_or("")
+    {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "text/javascript; charset=utf-8",
+        "xml" => "application/atom+xml; charset=utf-8",
+        "txt" => "text/plain; charset=utf-8",
+        "gmi" => crate::gemini::MIME_TYPE,
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn paths() {
+        let mut tree = OutputTree::new();
+        tree.insert_data("v1.2/notes", "text", "text/plain")
+            .unwrap();
+        tree.insert_data("img/dot.png", vec![0x89, 0x50, 0x4e, 0x47], "image/png")
+            .unwrap();
+        assert_eq!(tree.get("img/dot.png").unwrap().data().unwrap()[0], 0x89);
+
+        for path in ["", "/etc/passwd", "../up", "a//b", "a/./b", "a\\b", "dir/"] {
+            assert!(tree.insert_data(path, "", "text/plain").is_err(), "{path}");
+        }
+
+        let dir = TempDir::new("output");
+        let output = dir.join("site");
+        tree.write(&output).unwrap();
+        assert_eq!(
+            fs::read_to_string(output.join("v1.2/notes")).unwrap(),
+            "text"
+        );
+    }
+
+    #[test]
+    fn foreign_siblings() {
+        let dir = TempDir::new("sibling");
+        let output = dir.join("site");
+        let staging = sibling(&output, "new").unwrap();
+        fs::create_dir_all(&staging).unwrap();
+        fs::write(staging.join("keep"), "mine").unwrap();
+
+        // A directory that happens to have the name isn't removed.
+        let tree = OutputTree::new();
+        let err = tree.write(&output).unwrap_err();
+        assert!(err.to_string().contains("Refusing"), "{err}");
+        assert!(staging.join("keep").is_file());
+
+        // Leftovers of an interrupted build are.
+        fs::remove_file(staging.join("keep")).unwrap();
+        fs::write(staging.join(MARKER_FILE), MARKER_TEXT).unwrap();
+        fs::write(staging.join("partial"), "").unwrap();
+        tree.write(&output).unwrap();
+        assert!(!staging.exists());
+        assert!(output.join(MARKER_FILE).is_file());
+    }
+}
@@ -0,0 +1,287 @@
+//! Local web server for previewing a built site.
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use askama::Template;
+use notify::{RecursiveMode, Watcher};
+use percent_encoding::percent_decode_str;
+use tiny_http::{Header, Request, Response, Server};
+
+use crate::{lint::Diagnostic, output};
+
+/// URL path of the server-sent event stream that tells pages to reload.
+const RELOAD_PATH: &str = "/_reload";
+
+/// Script inserted into served HTML pages to reload them when the site is
+/// rebuilt.
+const RELOAD_SCRIPT: &str = r#"<script>
+new EventSource("/_reload").onmessage = () => location.reload();
+</script>"#;
+
+/// Page shown instead of the site while the latest build is failing.
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorPage<'a> {
+    diagnostics: &'a [Diagnostic],
+}
+
+/// Rebuild notifications for pages open in browsers.
+#[derive(Default, Debug)]
+pub struct LiveReload {
+    generation: Mutex<u64>,
+    changed: Condvar,
+    /// Error page for the latest build if it failed.
+    error_page: Mutex<Option<String>>,
+}
+
+impl LiveReload {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Tell all open pages to reload after a successful build.
+    pub fn reload(&self) {
+        *self.error_page.lock().expect("poisoned lock") = None;
+        self.notify();
+    }
+
+    /// Replace the pages with an error page showing the diagnostics of a
+    /// failed build until the next successful one.
+    pub fn fail(&self, diagnostics: &[Diagnostic]) {
+        let page = ErrorPage { diagnostics }
+            .render()
+            .unwrap_or_else(|e| format!("Build failed, could not show errors: {e}"));
+        *self.error_page.lock().expect("poisoned lock") = Some(page);
+        self.notify();
+    }
+
+    fn error_page(&self) -> Option<String> {
+        self.error_page.lock().expect("poisoned lock").clone()
+    }
+
+    fn notify(&self) {
+        let mut generation = self.generation.lock().expect("poisoned lock");
+        *generation += 1;
+        self.changed.notify_all();
+    }
+
+    fn generation(&self) -> u64 {
+        *self.generation.lock().expect("poisoned lock")
+    }
+
+    /// Block until the generation is newer than the given one.
+    fn wait(&self, seen: u64) {
+        let generation = self.generation.lock().expect("poisoned lock");
+        let _lock = self
+            .changed
+            .wait_while(generation, |a| *a <= seen)
+            .expect("poisoned lock");
+    }
+}
+
+/// Event stream body that sends a single reload event after the next
+/// rebuild and then ends.
+///
+/// Ending the response makes sure the event gets flushed to the browser,
+/// which is reloading the page at that point anyway.
+struct ReloadStream {
+    reload: Arc<LiveReload>,
+    seen: u64,
+    data: Option<io::Cursor<&'static [u8]>>,
+}
+
+impl Read for ReloadStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.data.is_none() {
+            self.reload.wait(self.seen);
+            self.data = Some(io::Cursor::new(b"data: reload\n\n"));
+        }
+        self.data.as_mut().map_or(Ok(0), |a| a.read(buf))
+    }
+}
+
+/// Serve the files under a directory at the given address in a background
+/// thread.
+///
+/// Like `try_files {path} {path}.html` in Caddy, extensionless URLs are
+/// served from the corresponding `.html` file. If live reload is given, HTML
+/// pages are made to reload themselves when it signals a rebuild, and are
+/// replaced with an error page while the latest build is failing.
+pub fn serve(
+    root: impl Into<PathBuf>,
+    address: &str,
+    reload: Option<Arc<LiveReload>>,
+) -> Result<thread::JoinHandle<()>> {
+    let root = root.into();
+    let server = Server::http(address).map_err(|e| anyhow!("Failed to start server: {e}"))?;
+
+    Ok(thread::spawn(move || {
+        for request in server.incoming_requests() {
+            match &reload {
+                Some(reload) if request.url() == RELOAD_PATH => {
+                    // Event streams stay open, give them their own threads.
+                    let stream = ReloadStream {
+                        reload: reload.clone(),
+                        seen: reload.generation(),
+                        data: None,
+                    };
+                    thread::spawn(move || {
+                        let headers = vec![
+                            header("Content-Type", "text/event-stream"),
+                            header("Cache-Control", "no-cache"),
+                        ];
+                        let response = Response::new(200.into(), headers, stream, None, None);
+                        let _ = request.respond(response);
+                    });
+                }
+                _ => respond(&root, request, reload.as_deref()),
+            }
+        }
+    }))
+}
+
+fn respond(root: &Path, request: Request, reload: Option<&LiveReload>) {
+    let path = resolve(root, request.url());
+    let is_page = path.as_ref().map_or(!request.url().contains('.'), |a| {
+        a.extension().is_some_and(|a| a == "html")
+    });
+
+    let response = match (reload.and_then(|a| a.error_page()), path) {
+        (Some(page), _) if is_page => {
+            let mut data = page.into_bytes();
+            inject_reload_script(&mut data);
+            Response::from_data(data)
+                .with_header(content_type(Path::new("error.html")))
+                .with_status_code(500)
+        }
+        (_, Some(path)) => match fs::read(&path) {
+            Ok(mut data) => {
+                if reload.is_some() && is_page {
+                    inject_reload_script(&mut data);
+                }
+                Response::from_data(data).with_header(content_type(&path))
+            }
+            Err(_) => Response::from_string("Not found").with_status_code(404),
+        },
+        (_, None) => Response::from_string("Not found").with_status_code(404),
+    };
+    // Don't bring down the server over a failed response.
+    let _ = request.respond(response);
+}
+
+fn inject_reload_script(html: &mut Vec<u8>) {
+    let pos = html
+        .windows(7)
+        .rposition(|a| a == b"</body>")
+        .unwrap_or(html.len());
+    html.splice(pos..pos, RELOAD_SCRIPT.bytes());
+}
+
+/// Find the file corresponding to an URL path.
+pub fn resolve(root: &Path, url: &str) -> Option<PathBuf> {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    let path = Path::new(path.trim_start_matches('/'));
+
+    // Do not allow escaping the root directory, or serving hidden files
+    // like the output marker that aren't part of the site.
+    let is_visible = |a: Component| match a {
+        Component::Normal(name) => !name.to_string_lossy().starts_with('.'),
+        _ => false,
+    };
+    if !path.components().all(is_visible) {
+        return None;
+    }
+
+    let path = root.join(path);
+    if path.is_dir() {
+        let index = path.join("index.html");
+        return index.is_file().then_some(index);
+    }
+    if path.is_file() {
+        return Some(path);
+    }
+
+    let mut html = path.into_os_string();
+    html.push(".html");
+    let html = PathBuf::from(html);
+    html.is_file().then_some(html)
+}
+
+/// Watch directories recursively and call `on_change` after files in them
+/// change. Runs until the watcher fails.
+///
+/// Bursts of changes, like an editor writing a file and a backup, are
+/// gathered into a single call. Directories that don't exist are skipped.
+pub fn watch(paths: &[impl AsRef<Path>], mut on_change: impl FnMut()) -> Result<()> {
+    let (send, recv) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = send.send(event);
+    })?;
+    for path in paths {
+        let path = path.as_ref();
+        if path.exists() {
+            watcher.watch(path, RecursiveMode::Recursive)?;
+        }
+    }
+
+    let is_change = |event: &notify::Event| {
+        use notify::EventKind::*;
+        matches!(event.kind, Create(_) | Modify(_) | Remove(_))
+    };
+
+    loop {
+        let event: notify::Event = recv.recv()??;
+        if !is_change(&event) {
+            continue;
+        }
+
+        // Wait for the changes to settle down.
+        thread::sleep(Duration::from_millis(100));
+        while recv.try_recv().is_ok() {}
+
+        on_change();
+    }
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field, value).expect("Invalid header")
+}
+
+fn content_type(path: &Path) -> Header {
+    header("Content-Type", output::mime_type(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn try_files() {
+        let dir = TempDir::new("serve");
+        let root = dir.path();
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(root.join("index.html"), "").unwrap();
+        fs::write(root.join("post.html"), "").unwrap();
+        fs::write(root.join("style.css"), "").unwrap();
+
+        assert_eq!(resolve(root, "/"), Some(root.join("index.html")));
+        assert_eq!(resolve(root, "/post"), Some(root.join("post.html")));
+        assert_eq!(resolve(root, "/post?tags=a"), Some(root.join("post.html")));
+        assert_eq!(resolve(root, "/style.css"), Some(root.join("style.css")));
+        assert_eq!(resolve(root, "/dir"), None);
+        assert_eq!(resolve(root, "/missing"), None);
+        assert_eq!(resolve(root, "/../index.html"), None);
+        fs::write(root.join(".blog-engine-output"), "").unwrap();
+        assert_eq!(resolve(root, "/.blog-engine-output"), None);
+    }
+}
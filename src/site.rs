@@ -1,50 +1,159 @@
 //! Output types that emit templates.
 
 use std::{
-    collections::{BTreeMap, BTreeSet},
-    fmt::Write,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    path::PathBuf,
+    sync::Arc,
 };
 
+use anyhow::{bail, Context, Result};
 use askama::Template;
-use serde::{Deserialize, Serialize};
-use serde_with::SerializeDisplay;
+use serde::Serialize;
 
 use crate::{
-    input::{self, Format},
-    util::{self, Outline},
-    Feed, Item, List,
+    djot,
+    feed::FeedEntry,
+    filter, gemini,
+    input::{self, Format, Status},
+    lint::{Lint, Lints},
+    markdown, org, outline,
+    output::{OutputTree, Page},
+    source::Source,
+    theme::Templates,
+    util::{self, ErrorLog},
+    Feed, Item, List, Options,
 };
 
-#[derive(Default, Debug, Serialize, Deserialize)]
-#[serde(from = "input::Site")]
-pub struct Site {
-    // Use the magic underscore name to tell the directory writer to flatten
-    // posts contents into the top level.
-    pub _posts: BTreeMap<String, Post>,
+/// Slugs of the generated lists and feeds that posts and pages can't use.
+const RESERVED_SLUGS: &[&str] = &["index", "links", "feed", "feed-links"];
 
-    #[serde(rename(serialize = "index.html"))]
-    pub index: List,
+/// The engine's default stylesheets, fonts and scripts.
+static ASSETS: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/static");
 
-    #[serde(rename(serialize = "feed.xml"))]
+#[derive(Default, Debug)]
+pub struct Site {
+    /// Posts by slug.
+    pub posts: BTreeMap<String, Post>,
+    /// Standalone pages by slug.
+    pub pages: BTreeMap<String, Post>,
+    pub index: List,
     pub feed: Feed,
-
-    #[serde(rename(serialize = "links.html"))]
     pub links: List,
-
-    #[serde(rename(serialize = "feed-links.xml"))]
     pub links_feed: Feed,
+    /// Base URL the site is being deployed to.
+    pub base_url: String,
+    /// Default author of the posts.
+    pub author: String,
+    /// Feed of the Gemini capsule, if the site has a Gemini URL.
+    pub gemini_feed: Option<Feed>,
+    /// Runtime templates of the site, the compiled templates are used if
+    /// not set.
+    pub templates: Option<Arc<Templates>>,
+    /// Directory of static files of the site that are added on top of the
+    /// engine's own.
+    pub static_dir: Option<PathBuf>,
+    /// Stylesheet for the syntax highlighting of code blocks.
+    pub highlight_css: String,
+    /// Filter command results of the build, saved for the next one.
+    pub filters: filter::Cache,
 }
 
-impl From<input::Site> for Site {
-    fn from(site: input::Site) -> Self {
-        let mut posts: BTreeMap<String, Post> = site
-            .posts
-            .iter()
-            .map(|(slug, ((data,), body))| {
-                let p = Post::new(&site.settings, slug, data, body);
-                (format!("{}.html", p.slug), p)
-            })
-            .collect();
+impl Site {
+    /// Build the output site from parsed site data.
+    ///
+    /// Posts that fail to render are skipped if the error log is in
+    /// keep-going mode. Drafts and posts dated in the future are left out
+    /// unless drafts are enabled in the options.
+    pub fn new(
+        site: &input::Site,
+        source: &Source,
+        options: &Options,
+        log: &mut ErrorLog,
+        lints: &mut Lints,
+    ) -> Result<Self> {
+        let chrome = Arc::new(Chrome::new(site));
+        let highlight_css = markdown::stylesheet(if site.settings.highlight_theme.is_empty() {
+            markdown::DEFAULT_THEME
+        } else {
+            &site.settings.highlight_theme
+        })
+        .map_err(|e| {
+            match source
+                .children(None)
+                .into_iter()
+                .find(|(a, _)| *a == "settings")
+            {
+                Some((_, i)) => source.error_at(source.attribute_line(i, "highlight-theme"), e),
+                None => e,
+            }
+        })?;
+
+        let mut filters = match &options.cache_dir {
+            Some(dir) => filter::Cache::load(dir),
+            None => Default::default(),
+        };
+
+        // Posts dated later than this are scheduled for the future.
+        let end_of_today = format!("{}T23:59:59Z", util::today());
+        let mut posts: BTreeMap<String, Post> = Default::default();
+        for (slug, ((data,), body)) in &site.posts {
+            let data = &complete_header(source, "posts", slug, data.clone(), body);
+            let is_scheduled =
+                util::is_valid_date(&data.date) && util::normalize_date(&data.date) > end_of_today;
+            let notice = match data.status {
+                Status::Draft => "Draft".to_owned(),
+                _ if is_scheduled => format!("Scheduled for {}", data.date),
+                _ => String::new(),
+            };
+            if !notice.is_empty() && !options.drafts {
+                continue;
+            }
+
+            let post = if RESERVED_SLUGS.contains(&slug.as_str()) {
+                Err(anyhow::anyhow!("The URL is reserved for a generated page"))
+            } else {
+                Post::new(&site.settings, slug, data, body, &mut filters)
+            };
+            let post = post.map(|mut post| {
+                if !notice.is_empty() {
+                    // Show unpublished posts in draft builds, but keep them
+                    // out of the feeds.
+                    post.status = Status::Draft;
+                    post.notice = notice;
+                }
+                post.chrome = chrome.clone();
+                post
+            });
+            let post =
+                post.map_err(|e| locate(source, source.find_entry("posts", slug), "post", slug, e));
+            if let Some(p) = log.recover(post)? {
+                lint_filter_output(source, lints, source.find_entry("posts", slug), &p.warnings);
+                posts.insert(p.slug.clone(), p);
+            }
+        }
+
+        let mut pages: BTreeMap<String, Post> = Default::default();
+        for (slug, ((data,), body)) in &site.pages {
+            let page = if site.posts.contains_key(slug) {
+                Err(anyhow::anyhow!("A post has the same URL"))
+            } else if RESERVED_SLUGS.contains(&slug.as_str()) {
+                Err(anyhow::anyhow!("The URL is reserved for a generated page"))
+            } else {
+                let data = complete_header(source, "pages", slug, data.into(), body);
+                Post::new(&site.settings, slug, &data, body, &mut filters)
+            };
+            let page = page
+                .map(|mut page| {
+                    page.id = slug.clone();
+                    page.chrome = chrome.clone();
+                    page
+                })
+                .map_err(|e| locate(source, source.find_entry("pages", slug), "page", slug, e));
+            if let Some(p) = log.recover(page)? {
+                lint_filter_output(source, lints, source.find_entry("pages", slug), &p.warnings);
+                pages.insert(p.slug.clone(), p);
+            }
+        }
 
         let mut topics: BTreeMap<String, BTreeSet<String>> = Default::default();
 
@@ -55,45 +164,76 @@ impl From<input::Site> for Site {
             }
         }
 
-        for (title, post) in posts.iter_mut() {
-            util::add_topics(title, &mut post.tags, &topics);
+        for post in posts.values_mut() {
+            let redundant = util::add_topics(&mut post.tags, &topics);
+            let line = source.find_entry("posts", &post.slug);
+            lint_redundant_tags(source, lints, line, &redundant);
         }
 
-        let index = List::new(
+        let mut index = List::new(
             &site.settings.site_name,
             "posts",
             "feed.xml",
-            posts.values().map(Item::new_post),
+            posts
+                .values()
+                .filter(|a| a.status != Status::Unlisted)
+                .map(Item::new_post),
         );
 
-        let mut links = List::new(
-            format!("{}: Bookmarks", site.settings.site_name),
-            "links",
-            "feed-links.xml",
-            site.links.iter().map(|(title, ((data,), content))| {
-                Item::new_bookmark(&site.settings, title, data, content)
-            }),
-        );
+        // Source lines of links by title, there may be more than one link
+        // with the same title.
+        let mut link_lines: BTreeMap<&str, VecDeque<usize>> = Default::default();
+        for (title, i) in source.entries("links") {
+            link_lines.entry(title).or_default().push_back(i);
+        }
 
         let mut seen_links: BTreeSet<String> = Default::default();
-        for link in links.items.iter_mut() {
+        let mut link_items = Vec::new();
+        for (title, ((data,), content)) in &site.links {
+            let line = link_lines
+                .get_mut(title.as_str())
+                .and_then(|a| a.pop_front());
+            let link = Item::new_bookmark(&site.settings, title, data, content, &mut filters)
+                .map_err(|e| locate(source, line, "link", title, e));
+            let Some(mut link) = log.recover(link)? else {
+                continue;
+            };
+            lint_filter_output(source, lints, line, &link.warnings);
+
             // Check for duplicate links
             if !link.url.is_empty() {
                 if seen_links.contains(&link.url) {
-                    eprintln!("Duplicate link URL: {}", link.url);
+                    lints.emit(
+                        Lint::DuplicateLink,
+                        source,
+                        line.map(|i| source.attribute_line(i, "uri")),
+                        format!("Duplicate link URL: {}", link.url),
+                    );
                 }
                 seen_links.insert(link.url.clone());
             }
 
-            util::add_topics(&link.title, &mut link.tags, &topics);
+            let redundant = util::add_topics(&mut link.tags, &topics);
+            lint_redundant_tags(source, lints, line, &redundant);
+            link_items.push(link);
         }
 
+        let mut links = List::new(
+            format!("{}: Bookmarks", site.settings.site_name),
+            "links",
+            "feed-links.xml",
+            link_items,
+        );
+
+        index.chrome = chrome.clone();
+        links.chrome = chrome;
+
         let feed = Feed::new(
             &site.settings.base_url,
             &site.settings.site_name,
             &site.settings.author,
             &format!("{}feed.xml", site.settings.base_url),
-            posts.values(),
+            posts.values().filter(|a| a.status == Status::Published),
         );
 
         let links_feed = Feed::new(
@@ -104,21 +244,296 @@ impl From<input::Site> for Site {
             &links.items,
         );
 
-        Site {
-            _posts: posts,
+        let gemini_url = &site.settings.gemini_url;
+        let gemini_feed = (!gemini_url.is_empty()).then(|| {
+            Feed::new(
+                gemini_url,
+                &site.settings.site_name,
+                &site.settings.author,
+                &format!("{gemini_url}feed.xml"),
+                posts
+                    .values()
+                    .filter(|a| a.status == Status::Published)
+                    .map(|a| FeedEntry {
+                        link: format!("{gemini_url}{}.gmi", a.slug),
+                        ..FeedEntry::from(a)
+                    }),
+            )
+        });
+
+        Ok(Site {
+            posts,
+            pages,
             index,
             feed,
             links,
             links_feed,
+            base_url: site.settings.base_url.clone(),
+            author: site.settings.author.clone(),
+            gemini_feed,
+            templates: None,
+            static_dir: None,
+            highlight_css,
+            filters,
+        })
+    }
+
+    /// The files of the site to write into the output directory, including
+    /// the static files.
+    pub fn output(&self) -> Result<OutputTree<'_>> {
+        let mut tree = self.tree();
+        for post in self.posts.values().chain(self.pages.values()) {
+            tree.insert_page(format!("{}.html", post.slug), post)?;
         }
+        tree.insert_page("index.html", &self.index)?;
+        tree.insert_page("feed.xml", &self.feed)?;
+        tree.insert_page("links.html", &self.links)?;
+        tree.insert_page("feed-links.xml", &self.links_feed)?;
+
+        tree.insert_data(
+            "highlight.css",
+            self.highlight_css.as_str(),
+            "text/css; charset=utf-8",
+        )?;
+        tree.insert_embedded(&ASSETS)?;
+        if let Some(dir) = &self.static_dir {
+            tree.insert_dir(dir)?;
+        }
+        Ok(tree)
     }
+
+    /// The files of the Gemini capsule version of the site.
+    pub fn gemini_output(&self) -> Result<OutputTree<'_>> {
+        let Some(feed) = &self.gemini_feed else {
+            bail!("Set `gemini-url` in the settings to write a Gemini capsule");
+        };
+        let mut tree = self.tree();
+        for post in self.posts.values().chain(self.pages.values()) {
+            let gemtext = gemini::post(post)
+                .with_context(|| format!("Failed to convert {} to gemtext", post.slug))?;
+            tree.insert_data(format!("{}.gmi", post.slug), gemtext, gemini::MIME_TYPE)?;
+        }
+        let index = gemini::list(&self.index, "feed.xml");
+        tree.insert_data("index.gmi", index, gemini::MIME_TYPE)?;
+        tree.insert_page("feed.xml", feed)?;
+        // The links feed only exists on the web site.
+        let links = gemini::list(&self.links, "");
+        tree.insert_data("links.gmi", links, gemini::MIME_TYPE)?;
+        Ok(tree)
+    }
+
+    fn tree(&self) -> OutputTree<'_> {
+        match &self.templates {
+            Some(templates) => OutputTree::with_templates(templates.clone()),
+            None => OutputTree::new(),
+        }
+    }
+}
+
+/// Page layout parts shared by all the HTML pages of the site.
+#[derive(Default, Debug, Hash, Serialize)]
+pub struct Chrome {
+    pub site_name: String,
+    /// Language tag of the site.
+    pub lang: String,
+    /// Links in the site header.
+    pub nav: Vec<NavLink>,
+    /// Links in the page footer.
+    pub footer: Vec<NavLink>,
+    pub license: Option<input::License>,
+}
+
+impl Chrome {
+    pub fn new(site: &input::Site) -> Self {
+        let settings = &site.settings;
+
+        let mut nav: Vec<NavLink> = if settings.nav.is_empty() {
+            vec![
+                NavLink::new("posts", ".", "posts"),
+                NavLink::new("links", "links", "links"),
+            ]
+        } else {
+            settings
+                .nav
+                .iter()
+                .map(|(url, title)| NavLink::new(title, url, nav_id(url)))
+                .collect()
+        };
+        for (slug, ((data,), _)) in &site.pages {
+            if data.nav {
+                let title = if data.title.is_empty() {
+                    util::unslugify(slug)
+                } else {
+                    data.title.clone()
+                };
+                nav.push(NavLink::new(title, slug, slug));
+            }
+        }
+
+        Chrome {
+            site_name: settings.site_name.clone(),
+            lang: if settings.lang.is_empty() {
+                "en".to_owned()
+            } else {
+                settings.lang.clone()
+            },
+            nav,
+            footer: settings
+                .footer
+                .iter()
+                .map(|(url, title)| NavLink::new(title, url, ""))
+                .collect(),
+            license: settings.license.clone(),
+        }
+    }
+}
+
+/// Identifier of the site's own page at a relative URL, so that configured
+/// navigation links get highlighted on the pages they point to.
+fn nav_id(url: &str) -> &str {
+    match url.trim_start_matches("./").trim_end_matches(".html") {
+        "" | "." | "index" => "posts",
+        path => path,
+    }
+}
+
+/// Link in the page header or footer.
+#[derive(Default, Debug, Hash, Serialize)]
+pub struct NavLink {
+    pub title: String,
+    pub url: String,
+    /// Identifier of the page the link points to, the link is highlighted on
+    /// that page. Empty for external links.
+    pub id: String,
 }
 
-#[derive(Clone, Default, Debug, Template, SerializeDisplay)]
+impl NavLink {
+    pub fn new(title: impl Into<String>, url: impl Into<String>, id: impl Into<String>) -> Self {
+        NavLink {
+            title: title.into(),
+            url: url.into(),
+            id: id.into(),
+        }
+    }
+}
+
+/// Convert a post body or link notes in a given format to HTML.
+///
+/// Messages from filter commands are added to `warnings`.
+pub(crate) fn render(
+    format: &Format,
+    text: &str,
+    settings: &input::Settings,
+    options: &input::MarkdownOptions,
+    toc: bool,
+    filters: &mut filter::Cache,
+    warnings: &mut Vec<String>,
+) -> Result<String> {
+    match format {
+        Format::Markdown => markdown::to_html(text, options, toc),
+        Format::Outline => outline::to_html(text, options),
+        Format::Org => org::to_html(text),
+        Format::Djot => djot::to_html(text),
+        Format::External(name) => {
+            settings.check_format(format)?;
+            let command = &settings.filters[name];
+            let filtered = filters.run(command, text)?;
+            if !filtered.stderr.is_empty() {
+                warnings.push(format!("Filter `{command}`: {}", filtered.stderr));
+            }
+            Ok(filtered.html)
+        }
+    }
+}
+
+/// Fill in a post or page header from the file the post is in.
+///
+/// The format comes from the file extension if the header doesn't set it,
+/// and Org documents can set the title and the date with keywords.
+fn complete_header(
+    source: &Source,
+    section: &str,
+    slug: &str,
+    mut data: input::PostHeader,
+    body: &str,
+) -> input::PostHeader {
+    if data.format.is_none() {
+        data.format = source
+            .find_entry(section, slug)
+            .and_then(|i| source.path(i).extension())
+            .and_then(|a| Format::from_extension(a.to_str()?));
+    }
+    if data.format == Some(Format::Org) {
+        org::fill_header(&mut data, body);
+    }
+    data
+}
+
+/// Attach the source location of a post, page or link to an error.
+///
+/// Unknown formats are reported at the item's `:format` line, malformed
+/// math at the body line with the math and other errors at the first body
+/// line.
+fn locate(
+    source: &Source,
+    entry: Option<usize>,
+    kind: &str,
+    name: &str,
+    e: anyhow::Error,
+) -> anyhow::Error {
+    let message = format!("Failed to render {kind} {name}: {e:#}");
+    let Some(i) = entry else {
+        return anyhow::anyhow!(message);
+    };
+    if e.chain().any(|a| a.is::<input::UnknownFormat>()) {
+        return source.error_at(source.attribute_line(i, "format"), message);
+    }
+    let body = source.body_lines(i);
+    let line = match e.downcast_ref::<markdown::MalformedMath>() {
+        Some(math) => {
+            let tex = math.0.lines().next().unwrap_or_default();
+            body.iter().find(|&&j| source.line_contains(j, tex))
+        }
+        None => None,
+    };
+    source.error_at(*line.or(body.first()).unwrap_or(&i), message)
+}
+
+/// Lint messages from the filter commands that rendered an item.
+fn lint_filter_output(
+    source: &Source,
+    lints: &mut Lints,
+    entry: Option<usize>,
+    warnings: &[String],
+) {
+    for message in warnings {
+        lints.emit(Lint::FilterOutput, source, entry, message);
+    }
+}
+
+/// Lint redundant tags at the tags field of an item.
+fn lint_redundant_tags(
+    source: &Source,
+    lints: &mut Lints,
+    entry: Option<usize>,
+    redundant: &[String],
+) {
+    if !redundant.is_empty() {
+        lints.emit(
+            Lint::RedundantTags,
+            source,
+            entry.map(|i| source.attribute_line(i, "tags")),
+            format!("Redundant topic tags: {}", redundant.join(", ")),
+        );
+    }
+}
+
+#[derive(Clone, Default, Debug, Hash, Serialize, Template)]
 #[template(path = "post.html")]
 pub struct Post {
     pub url: String,
-    /// Only used for lists, always empty for posts.
+    /// Identifier for template to highlight the navigation link to this
+    /// page, empty for posts.
     pub id: String,
     pub slug: String,
     pub title: String,
@@ -127,6 +542,26 @@ pub struct Post {
     pub feed_date: String,
     pub tags: Vec<String>,
     pub content: String,
+    pub status: Status,
+    /// Notice shown on unpublished posts in draft builds.
+    pub notice: String,
+    pub chrome: Arc<Chrome>,
+    /// Format of the post body, for converting it into other outputs.
+    #[serde(skip)]
+    pub format: Format,
+    /// Markdown extensions of the post body.
+    #[serde(skip)]
+    pub markdown: input::MarkdownOptions,
+    /// Source text of the post body.
+    #[serde(skip)]
+    pub body: String,
+    /// Messages from rendering the post that are reported as lints.
+    #[serde(skip)]
+    pub warnings: Vec<String>,
+}
+
+impl Page for Post {
+    const TEMPLATES: &'static [&'static str] = &["post.html", "base.html"];
 }
 
 impl Post {
@@ -135,8 +570,22 @@ impl Post {
         slug: &str,
         data: &input::PostHeader,
         body: &str,
-    ) -> Self {
-        Post {
+        filters: &mut filter::Cache,
+    ) -> Result<Self> {
+        let mut warnings = Vec::new();
+        let format = data.format.clone().unwrap_or_default();
+        let options = data.markdown.or(settings.markdown);
+        let content = render(
+            &format,
+            body,
+            settings,
+            &options,
+            data.toc,
+            filters,
+            &mut warnings,
+        )?;
+
+        Ok(Post {
             url: format!("{}{}", settings.base_url, slug),
             id: Default::default(),
             slug: slug.to_string(),
@@ -154,37 +603,253 @@ impl Post {
                 util::EPOCH.to_owned()
             },
             tags: data.tags.iter().cloned().map(String::from).collect(),
+            status: data.status,
+            notice: Default::default(),
+            chrome: Default::default(),
 
-            content: match data.format {
-                Format::Markdown => {
-                    // Convert markdown content to HTML.
-                    let mut html = String::new();
-                    pulldown_cmark::html::push_html(&mut html, pulldown_cmark::Parser::new(body));
-                    html
-                }
-                Format::Outline => {
-                    fn push(buf: &mut String, outline: &Outline) {
-                        if outline.0.is_empty() {
-                            return;
-                        }
-                        let _ = write!(buf, "<ul class='outline'>");
-                        for ((head,), body) in &outline.0 {
-                            if head.is_empty() {
-                                let _ = write!(buf, "<li><br/>");
-                            } else {
-                                let _ = write!(buf, "<li>{head}");
-                            }
-                            push(buf, body);
-                            let _ = write!(buf, "</li>");
-                        }
-                        let _ = write!(buf, "</ul>");
-                    }
-                    let body: Outline = idm::from_str(body).expect("Bad outline body");
-                    let mut ret = String::new();
-                    push(&mut ret, &body);
-                    ret
-                }
-            },
+            content,
+            format,
+            markdown: options,
+            body: body.to_owned(),
+            warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{self, TempDir};
+
+    fn build(options: &Options) -> Site {
+        let source = test_util::source(&[
+            ("posts/draft.md", ":status draft\n\nBody\n"),
+            ("posts/future.md", ":date 2999-01-01\n\nBody\n"),
+            ("posts/hidden.md", ":status unlisted\n\nBody\n"),
+            ("posts/public.md", ":date 2000-01-01\n\nBody\n"),
+        ]);
+        let site: input::Site = source.parse().unwrap();
+
+        let mut log = ErrorLog::new(false);
+        Site::new(&site, &source, options, &mut log, &mut Default::default()).unwrap()
+    }
+
+    #[test]
+    fn post_status() {
+        let site = build(&Default::default());
+        assert_eq!(
+            site.posts.keys().collect::<Vec<_>>(),
+            vec!["hidden", "public"]
+        );
+        assert_eq!(site.index.items.len(), 1);
+
+        let site = build(&Options {
+            drafts: true,
+            ..Default::default()
+        });
+        assert_eq!(site.posts.len(), 4);
+        assert_eq!(site.posts["future"].notice, "Scheduled for 2999-01-01");
+        assert_eq!(site.index.items.len(), 3);
+    }
+
+    #[test]
+    fn pages() {
+        let mut pages = vec![
+            ("pages/about.md", ":nav true\n\nBody\n"),
+            ("pages/colophon.md", ":title Colophon\n\nBody\n"),
+        ];
+        let source = test_util::source(&pages);
+        let site: input::Site = source.parse().unwrap();
+
+        let mut log = ErrorLog::new(false);
+        let site = Site::new(
+            &site,
+            &source,
+            &Default::default(),
+            &mut log,
+            &mut Default::default(),
+        )
+        .unwrap();
+        assert_eq!(site.pages.len(), 2);
+        assert!(site.index.items.is_empty());
+        let nav: Vec<_> = site.index.chrome.nav.iter().map(|a| &a.title).collect();
+        assert_eq!(nav, vec!["posts", "links", "About"]);
+
+        pages.push(("pages/links.md", "Body\n"));
+        let source = test_util::source(&pages);
+        let site: input::Site = source.parse().unwrap();
+        let err = Site::new(
+            &site,
+            &source,
+            &Default::default(),
+            &mut log,
+            &mut Default::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("reserved"), "{err}");
+        assert!(err.to_string().contains("links.md"), "{err}");
+    }
+
+    #[test]
+    fn unknown_formats() {
+        let source = test_util::source(&[
+            ("settings.idm", ":filters\n  rst pandoc -f rst\n"),
+            ("posts/a.md", ":title A\n:format rts\n\nBody\n"),
+            ("posts/b.md", ":title B\n\nBody\n"),
+        ]);
+        let site: input::Site = source.parse().unwrap();
+
+        // Items in unknown formats are errors, and only skipped when
+        // keeping going.
+        let mut log = ErrorLog::new(true);
+        let built = Site::new(
+            &site,
+            &source,
+            &Default::default(),
+            &mut log,
+            &mut Default::default(),
+        )
+        .unwrap();
+        assert_eq!(built.posts.keys().collect::<Vec<_>>(), vec!["b"]);
+        let diagnostic = crate::lint::Diagnostic::from(&log.errors()[0]);
+        assert_eq!(
+            diagnostic.to_string(),
+            "error: site/posts/a.md:2: Failed to render post a: \
+             Unknown format `rts`, did you mean `rst`?\n    2 | :format rts"
+        );
+
+        let mut log = ErrorLog::new(false);
+        assert!(Site::new(
+            &site,
+            &source,
+            &Default::default(),
+            &mut log,
+            &mut Default::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn render_error_lines() {
+        let source = test_util::source(&[
+            ("settings.idm", ":filters\n  fail false\n"),
+            (
+                "posts/math.md",
+                ":title Math\n\nFine $x$.\n\nBroken $\\left( x$ here.\n",
+            ),
+            ("posts/filter.md", ":title Filter\n:format fail\n\nBody\n"),
+        ]);
+        let site: input::Site = source.parse().unwrap();
+
+        let mut log = ErrorLog::new(true);
+        Site::new(
+            &site,
+            &source,
+            &Default::default(),
+            &mut log,
+            &mut Default::default(),
+        )
+        .unwrap();
+        let diagnostics: Vec<_> = log
+            .errors()
+            .iter()
+            .map(crate::lint::Diagnostic::from)
+            .collect();
+        let lines: Vec<_> = diagnostics
+            .iter()
+            .map(|a| a.location.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            vec!["site/posts/math.md:5", "site/posts/filter.md:4"]
+        );
+        assert!(diagnostics.iter().all(|a| !a.context.is_empty()));
+    }
+
+    #[test]
+    fn fallback_templates() {
+        let source = test_util::source(&[
+            (
+                "settings.idm",
+                ":site-name Tom & Jerry's\n:base-url https://example.com/blog/\n:author A. \"Quote\" Author\n\
+                 :nav\n  . posts\n  links bookmarks\n:footer\n  https://example.com/src source\n\
+                 :license\n  :name CC0\n  :url https://example.com/cc0\n  :badge cc0.svg\n",
+            ),
+            (
+                "posts/first.md",
+                ":title A <b>bold</b> & \"quoted\" post\n:date 2024-01-02\n:tags rust web\n:toc true\n\n\
+                 # Part\n\nText with [a link](https://example.com/a?b=c&d=e).\n",
+            ),
+            (
+                "posts/second.md",
+                ":date 2024-02-03T10:00:00Z\n:updated 2024-02-04\n:status unlisted\n\nBody\n",
+            ),
+            ("pages/about.md", ":nav true\n\nAbout me.\n"),
+            (
+                "links.idm",
+                "Example & co\n  :uri https://example.com/x?a=1&b=2\n  :date 2024-03-04\n  :tags web\n\n  \
+                 Notes with *markdown*.\n",
+            ),
+        ]);
+        let site: input::Site = source.parse().unwrap();
+        let mut log = ErrorLog::new(false);
+        let mut site = Site::new(
+            &site,
+            &source,
+            &Default::default(),
+            &mut log,
+            &mut Default::default(),
+        )
+        .unwrap();
+
+        // Pages and feeds by path.
+        let render = |site: &Site| -> BTreeMap<String, String> {
+            let tree = site.output().unwrap();
+            tree.iter()
+                .filter(|(path, _)| path.ends_with(".html") || path.ends_with(".xml"))
+                .map(|(path, file)| {
+                    let data = file.data().unwrap().into_owned();
+                    (path.to_owned(), String::from_utf8(data).unwrap())
+                })
+                .collect()
+        };
+        let compiled = render(&site);
+        let dir = TempDir::new("fallback");
+        site.templates = Some(Arc::new(Templates::load(dir.path()).unwrap().unwrap()));
+        let runtime = render(&site);
+
+        assert_eq!(compiled.len(), 7);
+        for (path, page) in &compiled {
+            assert_eq!(page, &runtime[path], "{path}");
         }
     }
+
+    #[test]
+    fn chrome() {
+        let source = test_util::source(&[(
+            "settings.idm",
+            ":nav\n  . home\n  now.html Now\n:license\n  :name CC0\n  :url https://example.com/\n",
+        )]);
+        let site: input::Site = source.parse().unwrap();
+
+        let chrome = Chrome::new(&site);
+        assert_eq!(chrome.lang, "en");
+        let ids: Vec<_> = chrome.nav.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, vec!["posts", "now"]);
+        assert_eq!(chrome.license.unwrap().name, "CC0");
+    }
+
+    #[test]
+    fn static_layering() {
+        let dir = TempDir::new("static");
+        std::fs::write(dir.join("style.css"), "body {}").unwrap();
+
+        let site = Site {
+            static_dir: Some(dir.path().to_owned()),
+            ..Default::default()
+        };
+        let tree = site.output().unwrap();
+        assert_eq!(&*tree.get("style.css").unwrap().data().unwrap(), b"body {}");
+        assert!(tree.get("view.js").is_some());
+    }
 }
@@ -0,0 +1,641 @@
+//! Site source text with a map back to the files it was read from.
+
+use std::{
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+
+/// A directory tree dumped into a single IDM expression.
+///
+/// Every line of the merged text remembers the file and line it came from,
+/// so errors from parsing the merged text can be reported against the
+/// original files.
+#[derive(Default, Debug)]
+pub struct Source {
+    text: String,
+    /// Origin of each line in `text`.
+    origins: Vec<Origin>,
+    files: Vec<SourceFile>,
+}
+
+#[derive(Debug)]
+struct SourceFile {
+    path: PathBuf,
+    lines: Vec<String>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Origin {
+    /// Byte offset of the line in the merged text.
+    start: usize,
+    file: usize,
+    /// One-based line in the file, zero for the headline generated from the
+    /// file or directory name.
+    line: usize,
+}
+
+/// Position in a site source file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    /// One-based line number, `None` when the location is the file itself.
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An `:key value` attribute line in the merged text.
+#[derive(Clone, Debug)]
+pub struct Attribute<'a> {
+    pub key: &'a str,
+    /// Attribute value, including any indented continuation lines.
+    pub value: String,
+    /// Zero-based line index in the merged text.
+    pub line: usize,
+}
+
+/// Number of source lines shown before and after an error line.
+const CONTEXT_LINES: usize = 2;
+
+/// Error located in a site source file.
+#[derive(Clone, Debug)]
+pub struct SourceError {
+    pub location: Location,
+    pub message: String,
+    /// Text of the offending source line, if known.
+    pub snippet: Option<String>,
+    /// Numbered source file lines around the offending line.
+    pub context: Vec<(usize, String)>,
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)?;
+        if let (Some(line), Some(snippet)) = (self.location.line, &self.snippet) {
+            write!(f, "\n{line:>5} | {snippet}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+impl SourceError {
+    /// Build an error for a line of a file that isn't part of the site data,
+    /// given the text of the file.
+    pub fn in_text(
+        path: impl Into<PathBuf>,
+        line: Option<usize>,
+        message: impl fmt::Display,
+        text: &str,
+    ) -> Self {
+        let lines: Vec<&str> = text.lines().collect();
+        SourceError {
+            location: Location {
+                path: path.into(),
+                line,
+            },
+            message: message.to_string(),
+            snippet: line
+                .and_then(|a| lines.get(a.wrapping_sub(1)))
+                .map(|a| a.to_string()),
+            context: line.map_or(Vec::new(), |a| context_lines(&lines, a)),
+        }
+    }
+}
+
+/// Top-level directories of the site directory that aren't site data.
+const RESERVED_DIRS: &[&str] = &["templates"];
+
+fn is_reserved(e: &walkdir::DirEntry) -> bool {
+    e.file_name()
+        .to_str()
+        .is_some_and(|a| RESERVED_DIRS.contains(&a))
+}
+
+/// Numbered lines around a one-based line.
+fn context_lines(lines: &[impl AsRef<str>], line: usize) -> Vec<(usize, String)> {
+    let start = line.saturating_sub(CONTEXT_LINES).max(1);
+    let end = (line + CONTEXT_LINES).min(lines.len());
+    (start..=end)
+        .map(|n| (n, lines[n - 1].as_ref().to_owned()))
+        .collect()
+}
+
+impl Source {
+    /// Read IDM, Markdown, Org and Djot files under a directory tree.
+    ///
+    /// Directories for things other than site data, like templates, are
+    /// skipped.
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut ret = Source::default();
+        let walk = walkdir::WalkDir::new(path)
+            .into_iter()
+            .filter_entry(|e| !(e.depth() == 1 && e.file_type().is_dir() && is_reserved(e)));
+        for e in walk {
+            let e = e?;
+            let depth = e.depth();
+            if depth == 0 {
+                // The root element, do not print out.
+                continue;
+            }
+
+            if e.file_type().is_dir() {
+                ret.push_dir(e.path(), depth - 1);
+            } else {
+                if !matches!(
+                    e.path()
+                        .extension()
+                        .map(|a| a.to_str().unwrap_or(""))
+                        .unwrap_or(""),
+                    "idm" | "md" | "org" | "dj"
+                ) {
+                    // Only read IDM and post files.
+                    continue;
+                }
+
+                let content = fs::read_to_string(e.path())
+                    .with_context(|| format!("Failed to read {}", e.path().display()))?;
+                ret.push_file(e.path(), depth - 1, &content);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    pub(crate) fn push_dir(&mut self, path: impl Into<PathBuf>, depth: usize) {
+        let path = path.into();
+        let name = path
+            .file_name()
+            .map(|a| a.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let file = self.files.len();
+        self.push_line(
+            depth,
+            &name,
+            Origin {
+                start: 0,
+                file,
+                line: 0,
+            },
+        );
+        self.files.push(SourceFile {
+            path,
+            lines: Vec::new(),
+        });
+    }
+
+    pub(crate) fn push_file(&mut self, path: impl Into<PathBuf>, depth: usize, content: &str) {
+        let path = path.into();
+        let name = path
+            .file_stem()
+            .map(|a| a.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let file = self.files.len();
+        self.push_line(
+            depth,
+            &name,
+            Origin {
+                start: 0,
+                file,
+                line: 0,
+            },
+        );
+
+        for (i, line) in content.lines().enumerate() {
+            let mut ln = line;
+            let mut depth = depth + 1;
+            // Turn tab indentation into spaces.
+            while let Some(rest) = ln.strip_prefix('\t') {
+                depth += 1;
+                ln = rest;
+            }
+            self.push_line(
+                depth,
+                ln,
+                Origin {
+                    start: 0,
+                    file,
+                    line: i + 1,
+                },
+            );
+        }
+
+        self.files.push(SourceFile {
+            path,
+            lines: content.lines().map(String::from).collect(),
+        });
+    }
+
+    fn push_line(&mut self, depth: usize, text: &str, mut origin: Origin) {
+        origin.start = self.text.len();
+        for _ in 0..depth {
+            self.text.push_str("  ");
+        }
+        self.text.push_str(text);
+        self.text.push('\n');
+        self.origins.push(origin);
+    }
+
+    /// Source location of a zero-based line in the merged text.
+    pub fn location(&self, line_idx: usize) -> Option<Location> {
+        let origin = self.origins.get(line_idx)?;
+        Some(Location {
+            path: self.files[origin.file].path.clone(),
+            line: (origin.line > 0).then_some(origin.line),
+        })
+    }
+
+    /// Names and line indices of the entries, such as posts or links, in a
+    /// top-level section of the site.
+    pub fn entries(&self, section: &str) -> Vec<(&str, usize)> {
+        match self.children(None).into_iter().find(|(a, _)| *a == section) {
+            Some((_, i)) => self.children(Some(i)),
+            None => Vec::new(),
+        }
+    }
+
+    /// Path of the file a line comes from.
+    pub fn path(&self, line_idx: usize) -> &Path {
+        &self.files[self.origins[line_idx].file].path
+    }
+
+    /// Line index of the first entry with the given name in a top-level
+    /// section of the site.
+    pub fn find_entry(&self, section: &str, name: &str) -> Option<usize> {
+        self.entries(section)
+            .into_iter()
+            .find(|(a, _)| *a == name)
+            .map(|(_, i)| i)
+    }
+
+    /// Text of the source file line at location.
+    pub fn snippet(&self, loc: &Location) -> Option<&str> {
+        let line = loc.line?;
+        let file = self.files.iter().find(|a| a.path == loc.path)?;
+        file.lines.get(line - 1).map(|a| a.as_str())
+    }
+
+    /// Numbered source file lines around location.
+    pub fn context(&self, loc: &Location) -> Vec<(usize, String)> {
+        let (Some(line), Some(file)) = (loc.line, self.files.iter().find(|a| a.path == loc.path))
+        else {
+            return Vec::new();
+        };
+        context_lines(&file.lines, line)
+    }
+
+    /// Build a located error for a zero-based line in the merged text.
+    pub fn located(&self, line_idx: usize, message: impl fmt::Display) -> Option<SourceError> {
+        let location = self.location(line_idx)?;
+        Some(SourceError {
+            snippet: self.snippet(&location).map(String::from),
+            context: self.context(&location),
+            location,
+            message: message.to_string(),
+        })
+    }
+
+    /// Build an error for a zero-based line in the merged text.
+    pub fn error_at(&self, line_idx: usize, message: impl fmt::Display) -> anyhow::Error {
+        match self.located(line_idx, &message) {
+            Some(e) => e.into(),
+            None => anyhow::anyhow!("{message}"),
+        }
+    }
+
+    /// Zero-based line of the merged text without the trailing newline.
+    fn line(&self, line_idx: usize) -> &str {
+        let start = self.origins[line_idx].start;
+        let end = self
+            .origins
+            .get(line_idx + 1)
+            .map_or(self.text.len(), |a| a.start);
+        &self.text[start..end - 1]
+    }
+
+    fn depth(&self, line_idx: usize) -> usize {
+        let line = self.line(line_idx);
+        (line.len() - line.trim_start_matches(' ').len()) / 2
+    }
+
+    /// Names and line indices of the lines in the merged text at depth one
+    /// below the given parent line, or of the top-level lines if parent is
+    /// `None`.
+    pub fn children(&self, parent: Option<usize>) -> Vec<(&str, usize)> {
+        let (depth, start) = match parent {
+            Some(i) => (self.depth(i) + 1, i + 1),
+            None => (0, 0),
+        };
+
+        let mut ret = Vec::new();
+        for i in start..self.origins.len() {
+            let line = self.line(i);
+            if line.trim().is_empty() {
+                continue;
+            }
+            match self.depth(i) {
+                d if d < depth => break,
+                d if d == depth => ret.push((line.trim(), i)),
+                _ => {}
+            }
+        }
+        ret
+    }
+
+    /// Attribute lines at the start of the body of the given line.
+    ///
+    /// If the parent is `None`, read attributes from the top level.
+    pub fn attributes(&self, parent: Option<usize>) -> Vec<Attribute<'_>> {
+        let (depth, start) = match parent {
+            Some(i) => (self.depth(i) + 1, i + 1),
+            None => (0, 0),
+        };
+
+        let mut ret: Vec<Attribute> = Vec::new();
+        for i in start..self.origins.len() {
+            let line = self.line(i);
+            if line.trim().is_empty() || self.depth(i) < depth {
+                break;
+            }
+
+            if self.depth(i) > depth {
+                // Attribute values can continue on indented lines below.
+                let Some(attr) = ret.last_mut() else {
+                    break;
+                };
+                if !attr.value.is_empty() {
+                    attr.value.push('\n');
+                }
+                attr.value.push_str(line.trim());
+                continue;
+            }
+
+            let Some(attr) = line.trim().strip_prefix(':') else {
+                break;
+            };
+            let (key, value) = attr.split_once(' ').unwrap_or((attr, ""));
+            ret.push(Attribute {
+                key,
+                value: value.trim().to_owned(),
+                line: i,
+            });
+        }
+        ret
+    }
+
+    /// Line indices of the body of an entry below its attributes, blank
+    /// lines left out.
+    pub fn body_lines(&self, entry: usize) -> Vec<usize> {
+        let depth = self.depth(entry) + 1;
+        let mut i = match self.attributes(Some(entry)).last() {
+            Some(attr) => attr.line + 1,
+            None => entry + 1,
+        };
+        // Skip the continuation lines of the last attribute.
+        while i < self.origins.len() && !self.line(i).trim().is_empty() && self.depth(i) > depth {
+            i += 1;
+        }
+
+        let mut ret = Vec::new();
+        for i in i..self.origins.len() {
+            if self.line(i).trim().is_empty() {
+                continue;
+            }
+            if self.depth(i) < depth {
+                break;
+            }
+            ret.push(i);
+        }
+        ret
+    }
+
+    /// Whether a zero-based line in the merged text contains a string.
+    pub fn line_contains(&self, line_idx: usize, text: &str) -> bool {
+        self.line(line_idx).contains(text)
+    }
+
+    /// Line index of an attribute of an entry, or of the entry itself if
+    /// the attribute isn't found.
+    pub fn attribute_line(&self, entry: usize, key: &str) -> usize {
+        self.attributes(Some(entry))
+            .iter()
+            .find(|a| a.key == key)
+            .map_or(entry, |a| a.line)
+    }
+
+    /// Deserialize the merged text.
+    ///
+    /// Errors are reported at the source file and line that caused them.
+    pub fn parse<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        let lines: Vec<(usize, &str)> = self.text.lines().enumerate().collect();
+        self.parse_lines(&lines).map_err(|(e, _)| e)
+    }
+
+    /// Deserialize the merged text, skipping entries in the given top-level
+    /// sections that fail to parse.
+    ///
+    /// Returns the errors for the skipped entries along with the value.
+    pub fn parse_skipping<T: DeserializeOwned>(
+        &self,
+        sections: &[&str],
+    ) -> anyhow::Result<(T, Vec<anyhow::Error>)> {
+        let mut lines: Vec<(usize, &str)> = self.text.lines().enumerate().collect();
+        let mut errors = Vec::new();
+        loop {
+            match self.parse_lines(&lines) {
+                Ok(ret) => return Ok((ret, errors)),
+                Err((e, Some(culprit))) if sections.contains(&lines[culprit.section].1) => {
+                    errors.push(e);
+                    lines.drain(culprit.entry);
+                }
+                Err((e, _)) => return Err(e),
+            }
+        }
+    }
+
+    /// Deserialize a subset of the merged text lines, given with their
+    /// indices in the merged text.
+    ///
+    /// If the error can be traced to a single entry, its position in `lines`
+    /// is returned with the error.
+    fn parse_lines<T: DeserializeOwned>(
+        &self,
+        lines: &[(usize, &str)],
+    ) -> Result<T, (anyhow::Error, Option<Culprit>)> {
+        let mut text = String::new();
+        for (_, line) in lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+
+        match idm::from_str(&text) {
+            Ok(ret) => Ok(ret),
+            Err(e) => {
+                let culprit = locate_error::<T>(lines);
+                // IDM line numbers are often missing or relative to a
+                // sub-expression, so only use them as a last resort.
+                let line_idx = culprit
+                    .as_ref()
+                    .map(|a| lines[a.line].0)
+                    .or_else(|| Some(lines.get(e.line_num()?.checked_sub(1)?)?.0));
+                match line_idx {
+                    Some(i) => Err((self.error_at(i, strip_line_num(&e)), culprit)),
+                    None => Err((e.into(), culprit)),
+                }
+            }
+        }
+    }
+}
+
+/// Positions of the lines that make deserialization fail.
+#[derive(Clone, Debug)]
+struct Culprit {
+    /// The failing line.
+    line: usize,
+    /// The top-level section headline.
+    section: usize,
+    /// All the lines of the failing entry.
+    entry: Range<usize>,
+}
+
+/// Find the line that makes deserialization fail.
+///
+/// Tries to parse each entry (each post or each link) of each top-level
+/// section alone to find the first bad entry, then finds the shortest prefix
+/// of that entry that still fails.
+fn locate_error<T: DeserializeOwned>(lines: &[(usize, &str)]) -> Option<Culprit> {
+    let fails = |idxs: &mut dyn Iterator<Item = usize>| {
+        let mut text = String::new();
+        for i in idxs {
+            text.push_str(lines[i].1);
+            text.push('\n');
+        }
+        idm::from_str::<T>(&text).is_err()
+    };
+
+    for (section, entries) in blocks(lines, 0, 0..lines.len()) {
+        let entries = blocks(lines, 1, entries);
+
+        // Find the first entry that fails on its own, or failing that, the
+        // first entry that makes the section fail so far. The starting line
+        // of the text to test is tracked along with the entry.
+        let bad = entries
+            .iter()
+            .find(|(head, body)| fails(&mut std::iter::once(section).chain(*head..body.end)))
+            .map(|(head, body)| (*head, body.end, *head))
+            .or_else(|| {
+                entries
+                    .iter()
+                    .find(|(_, body)| {
+                        fails(&mut std::iter::once(section).chain(section + 1..body.end))
+                    })
+                    .map(|(head, body)| (*head, body.end, section + 1))
+            });
+
+        if let Some((head, end, start)) = bad {
+            // Find the line where the entry starts to fail. A headline
+            // without a body may not parse, so start from the first body
+            // line.
+            let line = (head + 1..end)
+                .find(|&i| fails(&mut std::iter::once(section).chain(start..=i)))
+                .unwrap_or(head);
+            return Some(Culprit {
+                line,
+                section,
+                entry: head..end,
+            });
+        }
+
+        if fails(&mut std::iter::once(section)) {
+            return Some(Culprit {
+                line: section,
+                section,
+                entry: section..section + 1,
+            });
+        }
+    }
+
+    None
+}
+
+/// Split lines in range into blocks that start with a line at the given
+/// indentation depth. Returns the headline index and the range of the block
+/// body for each block.
+fn blocks(
+    lines: &[(usize, &str)],
+    depth: usize,
+    range: Range<usize>,
+) -> Vec<(usize, Range<usize>)> {
+    let indent = |s: &str| (s.len() - s.trim_start_matches(' ').len()) / 2;
+
+    let mut ret: Vec<(usize, Range<usize>)> = Vec::new();
+    for i in range {
+        let line = lines[i].1;
+        if !line.trim().is_empty() && indent(line) == depth {
+            ret.push((i, i + 1..i + 1));
+        } else if let Some((_, body)) = ret.last_mut() {
+            body.end = i + 1;
+        }
+    }
+    ret
+}
+
+/// Remove the line number prefix from an IDM error message.
+fn strip_line_num(e: &idm::Error) -> String {
+    let msg = e.to_string();
+    match msg.split_once(": ") {
+        Some((prefix, rest)) if prefix.starts_with("line ") => rest.to_owned(),
+        _ => msg,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input, test_util};
+
+    #[test]
+    fn locate_bad_header() {
+        let source = test_util::source(&[
+            ("posts/good.md", ":title Good\n\nBody\n"),
+            ("posts/bad.md", ":title Bad\n:format outlin\n\nBody\n"),
+        ]);
+
+        let err = source.parse::<input::Site>().unwrap_err();
+        let err = err.downcast_ref::<SourceError>().unwrap();
+        assert_eq!(
+            err.location,
+            Location {
+                path: "site/posts/bad.md".into(),
+                line: Some(2)
+            }
+        );
+        assert_eq!(err.snippet.as_deref(), Some(":format outlin"));
+    }
+
+    #[test]
+    fn skip_bad_entries() {
+        let source = test_util::source(&[
+            ("posts/a.md", ":title A\n\nBody\n"),
+            ("posts/b.md", ":format outlin\n\nBody\n"),
+            ("posts/c.md", ":title C\n\nBody\n"),
+        ]);
+
+        let (site, errors) = source.parse_skipping::<input::Site>(&["posts"]).unwrap();
+        assert_eq!(site.posts.keys().collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!(errors.len(), 1);
+    }
+}
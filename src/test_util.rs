@@ -0,0 +1,63 @@
+//! Helpers shared by the unit tests.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::source::Source;
+
+/// Temporary directory that is removed when it goes out of scope, even if
+/// the test fails.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create an empty directory for a test. The name and the process id
+    /// keep tests that run at the same time apart.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("blog-engine-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Site source with the given files, like a source directory would have.
+///
+/// Paths are relative to the `site` directory, and files in a subdirectory
+/// like `posts/a.md` go into that section. The files of a section must be
+/// next to each other.
+pub fn source(files: &[(&str, &str)]) -> Source {
+    let mut source = Source::default();
+    let mut section = None;
+    for &(path, content) in files {
+        match path.split_once('/') {
+            Some((dir, _)) => {
+                if section != Some(dir) {
+                    source.push_dir(format!("site/{dir}"), 0);
+                    section = Some(dir);
+                }
+                source.push_file(format!("site/{path}"), 1, content);
+            }
+            None => {
+                source.push_file(format!("site/{path}"), 0, content);
+                section = None;
+            }
+        }
+    }
+    source
+}
@@ -0,0 +1,166 @@
+//! Templates loaded at runtime from a site's `templates/` directory.
+//!
+//! Sites without a templates directory use the templates compiled into the
+//! engine. Templates that a site's directory doesn't override fall back to
+//! built-in versions in the runtime template syntax.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use minijinja::{AutoEscape, Environment, Output, State, UndefinedBehavior, Value};
+use serde::Serialize;
+
+use crate::source::SourceError;
+
+/// Built-in templates used for the templates a site doesn't override.
+const FALLBACKS: &[(&str, &str)] = &[
+    ("base.html", include_str!("../templates/jinja/base.html")),
+    ("feed.xml", include_str!("../templates/jinja/feed.xml")),
+    ("list.html", include_str!("../templates/jinja/list.html")),
+    ("post.html", include_str!("../templates/jinja/post.html")),
+];
+
+#[derive(Debug)]
+pub struct Templates {
+    env: Environment<'static>,
+    /// Template directory of the site.
+    dir: PathBuf,
+    /// Template sources by name, for error reports and change detection.
+    sources: BTreeMap<String, String>,
+    /// Templates that were read from the site's directory.
+    overridden: Vec<String>,
+}
+
+impl Templates {
+    /// Load the templates in a directory on top of the fallbacks.
+    ///
+    /// Returns `None` if the directory doesn't exist.
+    pub fn load(dir: impl AsRef<Path>) -> Result<Option<Self>> {
+        let dir = dir.as_ref();
+        if !dir.is_dir() {
+            return Ok(None);
+        }
+
+        let mut sources: BTreeMap<String, String> = FALLBACKS
+            .iter()
+            .map(|(name, text)| (name.to_string(), text.to_string()))
+            .collect();
+        let mut overridden = Vec::new();
+        for e in walkdir::WalkDir::new(dir) {
+            let e = e.with_context(|| format!("Failed to read {}", dir.display()))?;
+            if !e.file_type().is_file() {
+                continue;
+            }
+            let name = e
+                .path()
+                .strip_prefix(dir)?
+                .to_string_lossy()
+                .replace(std::path::MAIN_SEPARATOR, "/");
+            let text = fs::read_to_string(e.path())
+                .with_context(|| format!("Failed to read {}", e.path().display()))?;
+            sources.insert(name.clone(), text);
+            overridden.push(name);
+        }
+
+        let mut ret = Templates {
+            env: Environment::new(),
+            dir: dir.to_owned(),
+            sources,
+            overridden,
+        };
+        ret.env.set_undefined_behavior(UndefinedBehavior::Strict);
+        ret.env.set_formatter(escape);
+        for (name, text) in &ret.sources {
+            if let Err(e) = ret.env.add_template_owned(name.clone(), text.clone()) {
+                return Err(ret.error(e).into());
+            }
+        }
+
+        Ok(Some(ret))
+    }
+
+    /// Source text of a template.
+    pub fn source(&self, name: &str) -> Option<&str> {
+        self.sources.get(name).map(|a| a.as_str())
+    }
+
+    /// Render a template with the given context data.
+    pub fn render(&self, name: &str, context: &impl Serialize) -> Result<String> {
+        let template = self.env.get_template(name).map_err(|e| self.error(e))?;
+        Ok(template.render(context).map_err(|e| self.error(e))?)
+    }
+
+    /// Turn a template error into an error located in the template file.
+    fn error(&self, e: minijinja::Error) -> SourceError {
+        let name = e.name().unwrap_or_default();
+        let path = if self.overridden.iter().any(|a| a == name) {
+            self.dir.join(name)
+        } else {
+            Path::new("<built-in>").join(name)
+        };
+        let message = match e.detail() {
+            Some(detail) => format!("{}: {detail}", e.kind()),
+            None => e.kind().to_string(),
+        };
+        SourceError::in_text(
+            path,
+            e.line(),
+            message,
+            self.source(name).unwrap_or_default(),
+        )
+    }
+}
+
+/// Escape strings like the compiled templates do.
+///
+/// MiniJinja's own HTML escaping also escapes slashes, which would make the
+/// URLs differ from the compiled templates' output.
+fn escape(out: &mut Output, state: &State, value: &Value) -> Result<(), minijinja::Error> {
+    match value.as_str() {
+        Some(text) if state.auto_escape() == AutoEscape::Html && !value.is_safe() => {
+            write!(
+                out,
+                "{}",
+                askama::MarkupDisplay::new_unsafe(text, askama::Html)
+            )?;
+            Ok(())
+        }
+        _ => minijinja::escape_formatter(out, state, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn template_errors() {
+        let dir = TempDir::new("theme");
+        fs::write(
+            dir.join("post.html"),
+            "<h1>{{ title }}</h1>\n<p>{{ titel }}</p>\n",
+        )
+        .unwrap();
+
+        let templates = Templates::load(dir.path()).unwrap().unwrap();
+        let post = crate::Post {
+            title: "Title".into(),
+            ..Default::default()
+        };
+        let err = templates.render("post.html", &post).unwrap_err();
+        let err = err.downcast_ref::<SourceError>().unwrap();
+        assert_eq!(err.location.path, dir.join("post.html"));
+        assert_eq!(err.location.line, Some(2));
+
+        // Templates that aren't overridden fall back to the built-in ones.
+        let list = templates
+            .render("list.html", &crate::List::default())
+            .unwrap();
+        assert!(list.contains("<ul class=\"links\">"));
+    }
+}
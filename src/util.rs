@@ -1,15 +1,13 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
-    fmt,
-    fs::{self, File},
-    io::{self, prelude::*},
-    path::{Path, PathBuf},
+    fmt, fs,
+    path::Path,
     str::FromStr,
 };
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use lazy_regex::regex;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::DeserializeFromStr;
 use tldextract::{TldExtractor, TldResult};
 use url::Url;
@@ -101,19 +99,89 @@ impl From<Word> for String {
 /// winston churchill".
 ///
 /// ```
+/// # use blog_engine::util::unslugify;
 /// assert_eq!(unslugify("post-title"), "Post title");
 /// ```
 pub fn unslugify(slug: &str) -> String {
-    let mut ret = slug.replace('-', " ");
-    // Only ASCII-7 text supported for now.
-    ret[..1].make_ascii_uppercase();
+    let text = slug.replace('-', " ");
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => text,
+    }
+}
+
+/// Convert a title into a slug for a file name or an URL.
+///
+/// ```
+/// # use blog_engine::util::slugify;
+/// assert_eq!(slugify("Hello, world!"), "hello-world");
+/// ```
+pub fn slugify(title: &str) -> String {
+    let mut ret = String::new();
+    for word in title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|a| !a.is_empty())
+    {
+        if !ret.is_empty() {
+            ret.push('-');
+        }
+        ret.extend(word.chars().flat_map(char::to_lowercase));
+    }
     ret
 }
 
+/// Escape text for use in HTML content or attribute values.
+///
+/// ```
+/// # use blog_engine::util::escape_html;
+/// assert_eq!(escape_html("<a href=\"x\">"), "&lt;a href=&quot;x&quot;&gt;");
+/// ```
+pub fn escape_html(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&#39;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// Current UTC date in `YYYY-MM-DD` format.
+pub fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |a| a.as_secs());
+    let (y, m, d) = civil_from_days((secs / 86400) as i64);
+    format!("{y:04}-{m:02}-{d:02}")
+}
+
+/// Convert days since 1970-01-01 into a (year, month, day) date.
+///
+/// Uses Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
 /// Fill in missing parts of a partial date string that's only a year or only
 /// a year and a month. Default to start of the year or the month.
 ///
 /// ```
+/// # use blog_engine::util::normalize_date;
 /// assert_eq!(normalize_date("1984-03"), "1984-03-01T00:00:00Z");
 /// ```
 pub fn normalize_date(partial_date: &str) -> String {
@@ -125,113 +193,153 @@ pub fn normalize_date(partial_date: &str) -> String {
     ret
 }
 
-/// Dump a directory tree into a single IDM expression.
-pub fn read_directory(path: impl AsRef<Path>) -> Result<String, std::fmt::Error> {
-    use std::fmt::Write;
+/// Hard link a file to a new path, creating the directories on the path.
+///
+/// Falls back to copying the file with its modification time if the file
+/// can't be linked.
+pub fn link_file(from: impl AsRef<Path>, to: impl AsRef<Path>) -> anyhow::Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    if fs::hard_link(from, to).is_ok() {
+        return Ok(());
+    }
 
-    let mut ret = String::new();
-    for e in walkdir::WalkDir::new(path) {
-        let e = e.expect("read_path failed");
-        let depth = e.depth();
-        if depth == 0 {
-            // The root element, do not print out.
-            continue;
+    let copy = || -> std::io::Result<()> {
+        let modified = fs::metadata(from)?.modified()?;
+        fs::copy(from, to)?;
+        fs::File::options()
+            .write(true)
+            .open(to)?
+            .set_modified(modified)
+    };
+    copy().with_context(|| format!("Failed to copy {} to {}", from.display(), to.display()))
+}
+
+/// Write a file, creating the directories on its path.
+pub fn write_file(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Check that a date string is a full or partial ISO 8601 date understood by
+/// `normalize_date`.
+///
+/// ```
+/// # use blog_engine::util::is_valid_date;
+/// assert!(is_valid_date("1984-03"));
+/// assert!(!is_valid_date("1984-13-01"));
+/// ```
+pub fn is_valid_date(date: &str) -> bool {
+    let Some(caps) = regex!(r"^\d{4}(?:-(\d\d)(?:-(\d\d)(?:T\d\d:\d\d:\d\dZ)?)?)?$").captures(date)
+    else {
+        return false;
+    };
+
+    let in_range = |i, max| {
+        caps.get(i)
+            .is_none_or(|a| (1..=max).contains(&a.as_str().parse::<u32>().unwrap_or(0)))
+    };
+    in_range(1, 12) && in_range(2, 31)
+}
+
+/// Names of the fields of a struct type as seen by its `Deserialize`
+/// implementation.
+pub fn field_names<T: DeserializeOwned>() -> &'static [&'static str] {
+    use serde::de::{self, Visitor};
+
+    /// Deserializer that only records the fields it's asked for.
+    struct Introspect<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> de::Deserializer<'de> for Introspect<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("not a struct"))
         }
-        for _ in 1..depth {
-            write!(ret, "  ")?;
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("introspection only"))
         }
-        let is_dir = e.file_type().is_dir();
-        if is_dir {
-            writeln!(ret, "{}", e.file_name().to_string_lossy())?;
-        } else {
-            let path = Path::new(e.file_name());
-
-            if !matches!(
-                path.extension()
-                    .map(|a| a.to_str().unwrap_or(""))
-                    .unwrap_or(""),
-                "idm" | "md"
-            ) {
-                // Only read IDM and Markdown files.
-                continue;
-            }
 
-            let name = path
-                .file_stem()
-                .expect("read_path failed")
-                .to_string_lossy();
-            writeln!(ret, "{}", name)?;
-
-            // Print lines
-            let file = File::open(e.path()).expect("read_path: Open file failed");
-            for line in io::BufReader::new(file).lines() {
-                let line = line.expect("read_path failed");
-                let mut ln = &line[..];
-                let mut depth = depth;
-                // Turn tab indentation into spaces.
-                while ln.starts_with('\t') {
-                    depth += 1;
-                    ln = &ln[1..];
-                }
-                for _ in 1..(depth + 1) {
-                    write!(ret, "  ")?;
-                }
-                writeln!(ret, "{ln}")?;
-            }
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
         }
     }
 
-    Ok(ret)
+    let mut ret: &'static [&'static str] = &[];
+    let _ = T::deserialize(Introspect(&mut ret));
+    ret
 }
 
-/// Write a data structure into a directory tree.
-///
-/// Headlines with a period in them are interpreted as file names, anything
-/// above them is considered directories. Field names that start with
-/// underscore are flattened and their contents are written into the current
-/// level (like serde-flatten, but serde-flatten does not work well with IDM
-/// since it loses information about the value type).
+/// Find the closest match for a misspelled name.
+pub fn did_you_mean<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|a| (strsim::jaro_winkler(name, a), *a))
+        .filter(|(score, _)| *score > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, a)| a)
+}
+
+/// Error handling policy for site loading steps that can be skipped.
 ///
-/// Caveats are that directory names cannot contain periods and file names
-/// must contain periods.
-pub fn write_directory(root: impl AsRef<Path>, data: &impl Serialize) -> anyhow::Result<()> {
-    let output: String = idm::to_string(data)?;
-
-    // Read into tree
-    let tree: Outline = idm::from_str(&output)?;
-
-    // Fails if the dir doesn't exist, but this is ok. Ignore the result.
-    let _ = fs::remove_dir_all(&root);
-    fs::create_dir_all(&root)?;
-
-    fn write(path: impl AsRef<Path>, outline: &Outline) -> anyhow::Result<()> {
-        for ((head,), body) in &outline.0 {
-            if head.contains('.') {
-                // It's a file. Write it.
-                let path = path.as_ref().join(head);
-                if let Some(dir) = path.parent() {
-                    // It might contain some dirs too so create those first...
-                    fs::create_dir_all(dir)?;
-                }
-                fs::write(path, &body.to_string())?;
-            } else if head.starts_with('_') {
-                // HACK: Allow flattening things around a structural element
-                // if it's prefixed with an underscore.
-                write(path.as_ref(), body)?;
-            } else {
-                // Treat it as directory.
-                // If the name has slashes, they'll generate deeper subdirs.
-                let path: PathBuf = path.as_ref().join(head);
-                fs::create_dir_all(&path)?;
-                // Recurse into body using the new path.
-                write(path, body)?;
-            }
+/// In keep-going mode errors are collected, otherwise the first error aborts
+/// the build.
+#[derive(Default, Debug)]
+pub struct ErrorLog {
+    keep_going: bool,
+    errors: Vec<anyhow::Error>,
+}
+
+impl ErrorLog {
+    pub fn new(keep_going: bool) -> Self {
+        ErrorLog {
+            keep_going,
+            ..Default::default()
         }
+    }
 
+    /// Log an error in keep-going mode or return it otherwise.
+    pub fn push(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        if !self.keep_going {
+            return Err(error);
+        }
+        self.errors.push(error);
         Ok(())
     }
 
-    write(root, &tree)
+    /// Pass through a successful value, `None` if the error was logged.
+    pub fn recover<T>(&mut self, result: anyhow::Result<T>) -> anyhow::Result<Option<T>> {
+        match result {
+            Ok(a) => Ok(Some(a)),
+            Err(e) => self.push(e).map(|_| None),
+        }
+    }
+
+    pub fn errors(&self) -> &[anyhow::Error] {
+        &self.errors
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
 }
 
 /// Strip archive site prefixes from URL.
@@ -337,7 +445,14 @@ pub fn extract_site(url: &str) -> Option<String> {
     Some(domain)
 }
 
-pub fn add_topics(title: &str, tags: &mut Vec<String>, topics: &BTreeMap<String, BTreeSet<String>>) {
+/// Add the parent topics of tags from the tag hierarchy to the tag list.
+///
+/// Returns the tags that were already in the list but are implied by other
+/// tags.
+pub fn add_topics(
+    tags: &mut Vec<String>,
+    topics: &BTreeMap<String, BTreeSet<String>>,
+) -> Vec<String> {
     let mut new_tags: Vec<String> = Vec::new();
     let mut redundant: Vec<String> = Vec::new();
     for t in tags.iter() {
@@ -352,19 +467,12 @@ pub fn add_topics(title: &str, tags: &mut Vec<String>, topics: &BTreeMap<String,
         }
     }
 
-    // Print some lints.
-    // XXX: Lints could be printed with log::warn! instead
-    if !redundant.is_empty() {
-        eprintln!(
-            "{title}: Redundant topic tags: {} {tags:?}",
-            redundant.join(", ")
-        );
-    }
     new_tags.append(tags);
     *tags = new_tags;
+    redundant
 }
 
-#[derive(Debug)]
+#[derive(Debug, Hash, Serialize)]
 pub struct Tag {
     pub name: String,
     pub absolute_count: usize,
@@ -434,4 +542,18 @@ mod tests {
             assert_eq!(extract_site(a).unwrap(), b);
         }
     }
+
+    #[test]
+    fn unslugify_edge_cases() {
+        assert_eq!(unslugify("post-title"), "Post title");
+        assert_eq!(unslugify(""), "");
+        assert_eq!(unslugify("äiti-ja-isä"), "Äiti ja isä");
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
 }